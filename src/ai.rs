@@ -1,445 +1,955 @@
+use crate::book::{ply, Book, BookFilter, BookPolicy};
 use crate::color::Color;
 use crate::command::Move;
-use crate::database::DATABASE;
 use crate::play::*;
-use std::sync::mpsc::channel;
+use crate::tt::{hash, Bound, Entry, TransTable};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 const INF: i32 = i32::MAX;
 const THREAD_NUM: usize = 4;
-const DEPTH: i8 = 10;
-static mut COUNT: u8 = 60;
-static mut COLOR: bool = false;
-static mut TIME_LIMIT: SystemTime = SystemTime::UNIX_EPOCH;
-static mut IS_TIMEOUT: bool = false;
-static mut IS_FIRST_END: bool = false;
-
-pub fn set_time_remain(time: u64) {
-    unsafe {
-        let limit = COUNT as u64 * 900 + 5000;
-        let remain = if time > limit { time - limit } else { 0 };
-        println!("remain: {}", remain);
-        TIME_LIMIT = SystemTime::now() + Duration::from_millis(remain);
-    }
+const TT_BITS: u32 = 20;
+
+// 標準出力をプロトコルに使うモード (NBoard) では, 探索のログを標準エラー出力に回す
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn log_to_stderr(on: bool) {
+    LOG_TO_STDERR.store(on, Ordering::Relaxed);
+}
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if LOG_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EngineConfig {
+    pub depth: i8,
+    pub use_book: bool,
+    pub book_filter: BookFilter,
+    pub book_policy: BookPolicy,
+    pub weights: EvalWeights,
+    // 空きマスがこの数以下なら終盤は石差まで読み切り, それより多ければ勝敗だけを読む
+    pub exact_empties: u8,
 }
 
-pub fn init_ai(color: bool) {
-    unsafe {
-        COUNT = 64;
-        COLOR = color;
-        IS_TIMEOUT = false;
-        IS_FIRST_END = false;
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            depth: 10,
+            use_book: true,
+            book_filter: BookFilter::default(),
+            book_policy: BookPolicy::Frequency,
+            weights: EvalWeights::default(),
+            exact_empties: 14,
+        }
     }
 }
 
-fn read_final(board: &Board, color: Color, is_passed: bool) -> Option<i8> {
-    if unsafe { IS_TIMEOUT } {
-        println!("EXCEEDED");
-        return None;
+pub struct Engine {
+    config: EngineConfig,
+    book: Arc<Book>,
+    count: u8,
+    color: Color,
+    time_limit: SystemTime,
+    is_timeout: AtomicBool,
+    is_first_end: bool,
+    tt: TransTable,
+    // 直前の探索で選んだ手から始まる読み筋
+    pv: Mutex<Vec<Move>>,
+    counter: NodeCounter,
+}
+
+// 探索したノード数. 中盤探索 (nega_scout) と終盤の完全読み (read_final) の呼び出し回数と,
+// 評価関数を呼んだ回数 (中盤のノードにも数える) を全スレッドで合計する
+#[derive(Debug, Default)]
+struct NodeCounter {
+    mid: AtomicU64,
+    end: AtomicU64,
+    eval: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchStats {
+    pub mid: u64,
+    pub end: u64,
+    pub eval: u64,
+}
+
+impl SearchStats {
+    pub fn nodes(&self) -> u64 {
+        self.mid + self.end
     }
-    let valid_moves = valid_moves(board, color);
-    if valid_moves.is_empty() {
-        let res;
-        if is_passed {
-            res = get_result(board, color);
+
+    pub fn since(&self, start: &SearchStats) -> SearchStats {
+        SearchStats {
+            mid: self.mid - start.mid,
+            end: self.end - start.end,
+            eval: self.eval - start.eval,
+        }
+    }
+
+    pub fn nps(&self, elapsed: Duration) -> u64 {
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (self.nodes() as f64 / secs) as u64
         } else {
-            res = -read_final(board, !color, true)?;
+            0
         }
-        return Some(res);
     }
-    let mut nodes = vec![];
-    for action in valid_moves {
-        let mut n_board = board.clone();
-        do_move(&mut n_board, &Move::Mv(action.0, action.1), color);
-        nodes.push((calc_move_ordering_value(&n_board, color), n_board));
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes (mid {}, end {}, eval {})",
+            self.nodes(),
+            self.mid,
+            self.end,
+            self.eval
+        )
     }
-    nodes.sort_by(|a, b| b.0.cmp(&a.0));
-    let mut max_res = -1;
-    for (_, n_board) in nodes {
-        let res = -read_final(&n_board, !color, false)?;
-        if res == 1 {
-            return Some(1);
-        }
-        if res > max_res {
-            max_res = res;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SolveMode {
+    Wld,
+    Exact,
+}
+
+impl SolveMode {
+    fn window(&self) -> (i8, i8) {
+        match self {
+            SolveMode::Wld => (-1, 1),
+            SolveMode::Exact => (-64, 64),
         }
     }
-    Some(max_res)
 }
 
-fn read_final_action(color: Color, scores: &Vec<(i32, (i8, i8), Board)>) -> (i8, i8) {
-    if scores.is_empty() {
-        return (-1, -1);
-    }
-    let max_res = Arc::new(Mutex::new(-1));
-    let best_action = Arc::new(Mutex::new(scores.first().unwrap().1));
-    let mut handles = vec![];
-
-    let len = scores.len();
-    for i in 0..THREAD_NUM {
-        let mut chunk = vec![];
-        for j in (i..len).step_by(THREAD_NUM) {
-            chunk.push(*scores.get(j).unwrap());
-        }
-        let max_res = max_res.clone();
-        let best_action = best_action.clone();
-        handles.push(thread::spawn(move || {
-            for (_, action, n_board) in &chunk {
-                let res_val = read_final(&n_board, !color, false);
-                let res;
-                match res_val {
-                    Some(val) => res = -val,
-                    None => break,
-                }
-                let mut max_res_p = max_res.lock().unwrap();
-                if res > *max_res_p {
-                    *max_res_p = res;
-                    *best_action.lock().unwrap() = *action;
-                }
-                if *max_res_p == 1 {
-                    break;
-                }
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Engine {
+        let mut engine = Engine {
+            config,
+            book: Arc::new(Book::new()),
+            count: 64,
+            color: false,
+            time_limit: SystemTime::UNIX_EPOCH,
+            is_timeout: AtomicBool::new(false),
+            is_first_end: false,
+            tt: TransTable::new(TT_BITS),
+            pv: Mutex::new(vec![]),
+            counter: NodeCounter::default(),
+        };
+        // 中盤探索は depth - 4 手も読むので, 5 未満にすると探索が止まらない
+        engine.set_depth(engine.config.depth);
+        engine
+    }
+
+    pub fn set_book(&mut self, book: Arc<Book>) {
+        self.book = book;
+    }
+
+    pub fn book(&self) -> &Book {
+        &self.book
+    }
+
+    // 他と共有しているときは複製してから書き換える
+    pub fn book_mut(&mut self) -> &mut Book {
+        Arc::make_mut(&mut self.book)
+    }
+
+    // play の中盤探索は depth - 4 から読むので 5 未満にはしない
+    pub fn set_depth(&mut self, depth: i8) {
+        self.config.depth = depth.max(5);
+    }
+
+    pub fn depth(&self) -> i8 {
+        self.config.depth
+    }
+
+    pub fn weights(&self) -> &EvalWeights {
+        &self.config.weights
+    }
+
+    pub fn book_filter(&self) -> &BookFilter {
+        &self.config.book_filter
+    }
+
+    // これまでに探索したノード数の合計. 差を取って1回の探索の分を求める
+    pub fn stats(&self) -> SearchStats {
+        SearchStats {
+            mid: self.counter.mid.load(Ordering::Relaxed),
+            end: self.counter.end.load(Ordering::Relaxed),
+            eval: self.counter.eval.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn pv(&self) -> Vec<Move> {
+        self.pv.lock().unwrap().clone()
+    }
+
+    pub fn set_time_remain(&mut self, time: u64) {
+        let limit = self.count as u64 * 900 + 5000;
+        let remain = if time > limit { time - limit } else { 0 };
+        log!("remain: {}", remain);
+        self.time_limit = SystemTime::now() + Duration::from_millis(remain);
+    }
+
+    pub fn init_ai(&mut self, color: bool) {
+        self.count = 64;
+        self.color = color;
+        self.is_timeout.store(false, Ordering::Relaxed);
+        self.is_first_end = false;
+        self.tt.clear();
+    }
+
+    pub fn init_ai_from(&mut self, color: bool, board: &Board) {
+        self.init_ai(color);
+        self.count = 68 - count(board.0 | board.1) as u8;
+    }
+
+    // mover が打った直後の board を相手番から depth 手読み, mover から見た評価値を返す
+    pub fn search_value(&self, board: &Board, mover: Color, depth: i8) -> i32 {
+        self.nega_scout(
+            board,
+            board,
+            !mover,
+            -INF,
+            INF,
+            depth,
+            depth,
+            false,
+            &mut vec![],
+        )
+        .map_or(0, |v| -v)
+    }
+
+    fn watch_timeout(&self, rx: Receiver<bool>) {
+        loop {
+            thread::sleep(std::time::Duration::from_millis(10));
+            if SystemTime::now() >= self.time_limit {
+                self.is_timeout.store(true, Ordering::Relaxed);
+                break;
             }
-        }));
-    }
-    let (tx, rx) = channel();
-    let timer = thread::spawn(move || loop {
-        thread::sleep(std::time::Duration::from_millis(10));
-        if SystemTime::now() >= unsafe { TIME_LIMIT } {
-            unsafe {
-                IS_TIMEOUT = true;
+            match rx.try_recv() {
+                Ok(_) => break,
+                _ => continue,
             }
-            break;
         }
-        match rx.try_recv() {
-            Ok(_) => break,
-            _ => continue,
-        }
-    });
-    for handle in handles {
-        handle.join().unwrap();
     }
-    tx.send(true).unwrap_or(());
-    timer.join().unwrap();
-    let score = *max_res.clone().lock().unwrap();
-    println!("Score: {}", score);
-    return *best_action.clone().lock().unwrap();
-}
 
-pub fn play(board: &Board, color: Color, _is_passed: bool) -> Move {
-    let mut best_action: (i8, i8);
-    unsafe {
-        IS_TIMEOUT = false;
+    fn read_final(
+        &self,
+        board: &Board,
+        color: Color,
+        alpha: i8,
+        beta: i8,
+        is_passed: bool,
+    ) -> Option<i8> {
+        if self.is_timeout.load(Ordering::Relaxed) {
+            log!("EXCEEDED");
+            return None;
+        }
+        self.counter.end.fetch_add(1, Ordering::Relaxed);
+        let valid_moves = valid_moves(board, color);
+        if valid_moves.is_empty() {
+            let res;
+            if is_passed {
+                res = get_disc_diff(board, color);
+            } else {
+                res = -self.read_final(board, !color, -beta, -alpha, true)?;
+            }
+            return Some(res);
+        }
+        let mut nodes = vec![];
+        for action in valid_moves {
+            let mut n_board = board.clone();
+            do_move(&mut n_board, &Move::Mv(action.0, action.1), color);
+            nodes.push((calc_move_ordering_value(&n_board, color), n_board));
+        }
+        nodes.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut alpha = alpha;
+        let mut max_res = -64;
+        for (_, n_board) in nodes {
+            let res = -self.read_final(&n_board, !color, -beta, -alpha, false)?;
+            if beta <= res {
+                return Some(res);
+            }
+            if alpha < res {
+                alpha = res;
+            }
+            if res > max_res {
+                max_res = res;
+            }
+        }
+        Some(max_res)
     }
-    if unsafe { COUNT >= 41 && !IS_FIRST_END } {
-        best_action = apply_joseki(board, color);
-        if best_action.0 == -1 {
-            unsafe { IS_FIRST_END = true };
-            best_action = nega_scout_action(board, color, vec![DEPTH - 4, DEPTH - 1, DEPTH]);
-        }
-    } else if unsafe { COUNT >= 25 } {
-        best_action = nega_scout_action(board, color, vec![DEPTH - 4, DEPTH - 1, DEPTH]);
-    } else if unsafe { COUNT > DEPTH as u8 } {
-        println!("final1");
-        let scores =
-            nega_scout_actions(board, color, DEPTH, &get_move_ordering_score(board, color));
+
+    fn read_final_action(
+        &self,
+        color: Color,
+        scores: &Vec<(i32, (i8, i8), Board)>,
+        mode: SolveMode,
+    ) -> (i8, i8) {
         if scores.is_empty() {
-            best_action = (-1, -1);
-        } else if unsafe { IS_TIMEOUT } {
-            best_action = scores.first().unwrap().1;
+            return (-1, -1);
+        }
+        let start = (self.stats(), Instant::now());
+        let (lo, hi) = mode.window();
+        let max_res = Arc::new(Mutex::new(lo));
+        let best_action = Arc::new(Mutex::new(scores.first().unwrap().1));
+
+        let len = scores.len();
+        thread::scope(|s| {
+            let mut handles = vec![];
+            for i in 0..THREAD_NUM {
+                let mut chunk = vec![];
+                for j in (i..len).step_by(THREAD_NUM) {
+                    chunk.push(*scores.get(j).unwrap());
+                }
+                let max_res = max_res.clone();
+                let best_action = best_action.clone();
+                handles.push(s.spawn(move || {
+                    for (_, action, n_board) in &chunk {
+                        let alpha = *max_res.lock().unwrap();
+                        let res_val = self.read_final(&n_board, !color, -hi, -alpha, false);
+                        let res;
+                        match res_val {
+                            Some(val) => res = -val,
+                            None => break,
+                        }
+                        let mut max_res_p = max_res.lock().unwrap();
+                        if res > *max_res_p {
+                            *max_res_p = res;
+                            *best_action.lock().unwrap() = *action;
+                        }
+                        if *max_res_p >= hi {
+                            break;
+                        }
+                    }
+                }));
+            }
+            let (tx, rx) = channel();
+            let timer = s.spawn(move || self.watch_timeout(rx));
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            tx.send(true).unwrap_or(());
+            timer.join().unwrap();
+        });
+        let score = *max_res.clone().lock().unwrap();
+        match mode {
+            SolveMode::Wld => log!("Score: {}", score.signum()),
+            SolveMode::Exact => log!("Score: {}", score),
+        }
+        let stats = self.stats().since(&start.0);
+        let elapsed = start.1.elapsed();
+        log!(
+            "Nodes: {}, {} ms, {} nps",
+            stats,
+            elapsed.as_millis(),
+            stats.nps(elapsed)
+        );
+        return *best_action.clone().lock().unwrap();
+    }
+
+    fn final_mode(&self) -> SolveMode {
+        // count は空きマス + 4
+        if self.count <= self.config.exact_empties.saturating_add(4) {
+            SolveMode::Exact
         } else {
-            best_action = read_final_action(color, &scores)
+            SolveMode::Wld
         }
-    } else {
-        println!("final2");
-        let scores = get_move_ordering_score(board, color);
-        best_action = read_final_action(color, &scores);
     }
-    unsafe {
-        COUNT -= 2;
+
+    fn search_action(&self, board: &Board, color: Color) -> (i8, i8) {
+        let depth = self.config.depth;
+        if self.count >= 25 {
+            self.nega_scout_action(board, color, vec![depth - 4, depth - 1, depth])
+        } else if self.count > depth as u8 {
+            log!("final1");
+            let scores = self.nega_scout_actions(
+                board,
+                color,
+                depth,
+                &get_move_ordering_score(board, color),
+            );
+            if scores.is_empty() {
+                (-1, -1)
+            } else if self.is_timeout.load(Ordering::Relaxed) {
+                scores.first().unwrap().1
+            } else {
+                self.read_final_action(color, &scores, self.final_mode())
+            }
+        } else {
+            log!("final2");
+            let scores = get_move_ordering_score(board, color);
+            self.read_final_action(color, &scores, self.final_mode())
+        }
     }
-    if best_action.0 == -1 {
-        Move::Pass
-    } else {
-        Move::Mv(best_action.0, best_action.1)
+
+    pub fn play(&mut self, board: &Board, color: Color, _is_passed: bool) -> Move {
+        self.is_timeout.store(false, Ordering::Relaxed);
+        // book を引くのは book の深さまで. init_ai_from で途中の局面から始めたときも
+        // 終盤は残りの手数で完全読みに進む
+        let mut best_action = (-1, -1);
+        if self.config.use_book && !self.is_first_end && ply(board) < self.config.book_filter.depth
+        {
+            best_action = apply_joseki(
+                &self.book,
+                &self.config.book_filter,
+                self.config.book_policy,
+                board,
+                color,
+            );
+            if best_action.0 == -1 {
+                self.is_first_end = true;
+            }
+        }
+        if best_action.0 == -1 {
+            best_action = self.search_action(board, color);
+        }
+        self.count -= 2;
+        // book や終盤の完全読みで選んだ手には読み筋がない
+        let mut pv = self.pv.lock().unwrap();
+        if pv.first() != Some(&Move::Mv(best_action.0, best_action.1)) {
+            *pv = if best_action.0 == -1 {
+                vec![]
+            } else {
+                vec![Move::Mv(best_action.0, best_action.1)]
+            };
+        }
+        if best_action.0 == -1 {
+            Move::Pass
+        } else {
+            Move::Mv(best_action.0, best_action.1)
+        }
     }
-}
 
-fn calc_move_ordering_value(board: &Board, color: Color) -> i32 {
-    -(count(valid_mask(board, !color)) as i32)
-}
+    // pv には board から始まる読み筋を書き込む. 子の読み筋を受け取って先頭に手を足していく
+    // (三角 PV テーブルを局面ごとの Vec で持つ形)
+    fn nega_scout(
+        &self,
+        board: &Board,
+        board_p: &Board,
+        color: Color,
+        alpha: i32,
+        beta: i32,
+        depth: i8,
+        initial_depth: i8,
+        is_passed: bool,
+        pv: &mut Vec<Move>,
+    ) -> Option<i32> {
+        pv.clear();
+        if self.is_timeout.load(Ordering::Relaxed) {
+            log!("EXCEEDED");
+            return None;
+        }
+        self.counter.mid.fetch_add(1, Ordering::Relaxed);
+        if depth == 0 {
+            self.counter.eval.fetch_add(1, Ordering::Relaxed);
+            return Some(evaluate(
+                board,
+                board_p,
+                color,
+                self.count as i8 + depth - initial_depth,
+                &self.config.weights,
+            ));
+        }
+        let valid_moves = valid_moves(board, color);
+        let mut alpha = alpha;
 
-fn nega_scout(
-    board: &Board,
-    board_p: &Board,
-    color: Color,
-    alpha: i32,
-    beta: i32,
-    depth: i8,
-    initial_depth: i8,
-    is_passed: bool,
-) -> Option<i32> {
-    if unsafe { IS_TIMEOUT } {
-        println!("EXCEEDED");
-        return None;
-    }
-    if depth == 0 {
-        return Some(evaluate(
-            board,
-            board_p,
-            color,
-            unsafe { COUNT as i8 } + depth - initial_depth,
-        ));
-    }
-    let valid_moves = valid_moves(board, color);
-    let mut alpha = alpha;
+        if valid_moves.is_empty() {
+            if is_passed {
+                let res = get_result(board, color);
+                return Some(res as i32 * INF);
+            }
+            let mut child_pv = vec![];
+            let v = -self.nega_scout(
+                board,
+                board_p,
+                !color,
+                -beta,
+                -alpha,
+                depth - 1,
+                initial_depth,
+                true,
+                &mut child_pv,
+            )?;
+            pv.push(Move::Pass);
+            pv.append(&mut child_pv);
+            return Some(v);
+        }
 
-    if valid_moves.is_empty() {
-        if is_passed {
-            let res = get_result(board, color);
-            return Some(res as i32 * INF);
+        let key = hash(board, color);
+        let mut hash_move = None;
+        if let Some(entry) = self.tt.probe(key) {
+            if entry.depth >= depth {
+                match entry.bound {
+                    // 置換表で打ち切ったところから先は置換表の最善手でつなぐ
+                    Bound::Exact => {
+                        *pv = self.tt_pv(board, color, depth);
+                        return Some(entry.score);
+                    }
+                    Bound::Lower if beta <= entry.score => return Some(entry.score),
+                    Bound::Upper if entry.score <= alpha => return Some(entry.score),
+                    _ => {}
+                }
+            }
+            hash_move = entry.best;
         }
-        return Some(-nega_scout(
-            board,
-            board_p,
-            !color,
-            -beta,
-            -alpha,
-            depth - 1,
-            initial_depth,
-            true,
-        )?);
-    }
+        let alpha_orig = alpha;
+        let store = |score: i32, bound: Bound, best: (i8, i8)| {
+            self.tt.store(
+                key,
+                Entry {
+                    score,
+                    depth,
+                    bound,
+                    best: Some(best),
+                },
+            );
+        };
+        let set_pv = |pv: &mut Vec<Move>, action: (i8, i8), child_pv: &mut Vec<Move>| {
+            pv.clear();
+            pv.push(Move::Mv(action.0, action.1));
+            pv.append(child_pv);
+        };
 
-    let mut nodes = vec![];
-    for action in valid_moves {
-        let mut n_board = board.clone();
-        do_move(&mut n_board, &Move::Mv(action.0, action.1), color);
-        nodes.push((calc_move_ordering_value(&n_board, color), n_board));
-    }
-    nodes.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut nodes = vec![];
+        for action in valid_moves {
+            let mut n_board = board.clone();
+            do_move(&mut n_board, &Move::Mv(action.0, action.1), color);
+            nodes.push((calc_move_ordering_value(&n_board, color), action, n_board));
+        }
+        nodes.sort_by(|a, b| b.0.cmp(&a.0));
+        if let Some(i) = nodes.iter().position(|node| Some(node.1) == hash_move) {
+            let node = nodes.remove(i);
+            nodes.insert(0, node);
+        }
 
-    let (first, trail) = nodes.split_first().unwrap();
-    let v = -nega_scout(
-        &first.1,
-        board,
-        !color,
-        -beta,
-        -alpha,
-        depth - 1,
-        initial_depth,
-        false,
-    )?;
-    let mut max = v;
-    if beta <= v {
-        return Some(v);
-    }
-    if alpha < v {
-        alpha = v;
-    }
-
-    for (_, nboard) in trail {
-        let mut score = -nega_scout(
-            nboard,
+        let (first, trail) = nodes.split_first().unwrap();
+        let mut child_pv = vec![];
+        let v = -self.nega_scout(
+            &first.2,
             board,
             !color,
-            -alpha - 1,
+            -beta,
             -alpha,
             depth - 1,
             initial_depth,
             false,
+            &mut child_pv,
         )?;
-        if beta <= score {
-            return Some(score);
+        let mut max = v;
+        let mut best_action = first.1;
+        set_pv(pv, best_action, &mut child_pv);
+        if beta <= v {
+            store(v, Bound::Lower, best_action);
+            return Some(v);
         }
-        if alpha < score {
-            alpha = score;
-            score = -nega_scout(
+        if alpha < v {
+            alpha = v;
+        }
+
+        for (_, action, nboard) in trail {
+            let mut score = -self.nega_scout(
                 nboard,
                 board,
                 !color,
-                -beta,
+                -alpha - 1,
                 -alpha,
                 depth - 1,
                 initial_depth,
                 false,
+                &mut child_pv,
             )?;
             if beta <= score {
+                set_pv(pv, *action, &mut child_pv);
+                store(score, Bound::Lower, *action);
                 return Some(score);
             }
             if alpha < score {
                 alpha = score;
+                score = -self.nega_scout(
+                    nboard,
+                    board,
+                    !color,
+                    -beta,
+                    -alpha,
+                    depth - 1,
+                    initial_depth,
+                    false,
+                    &mut child_pv,
+                )?;
+                if beta <= score {
+                    set_pv(pv, *action, &mut child_pv);
+                    store(score, Bound::Lower, *action);
+                    return Some(score);
+                }
+                if alpha < score {
+                    alpha = score;
+                }
+            }
+            if max < score {
+                max = score;
+                best_action = *action;
+                set_pv(pv, best_action, &mut child_pv);
             }
         }
-        if max < score {
-            max = score
+        if max <= alpha_orig {
+            store(max, Bound::Upper, best_action);
+        } else {
+            store(max, Bound::Exact, best_action);
         }
+        Some(max)
     }
-    Some(max)
-}
 
-pub fn get_move_ordering_score(board: &Board, color: Color) -> Vec<(i32, (i8, i8), Board)> {
-    let valid_moves = valid_moves(board, color);
-    let mut nodes = vec![];
-    for action in valid_moves {
-        let mut n_board = board.clone();
-        do_move(&mut n_board, &Move::Mv(action.0, action.1), color);
-        nodes.push((calc_move_ordering_value(&n_board, color), action, n_board));
-    }
-    nodes.sort_by(|a, b| b.0.cmp(&a.0));
-    nodes
-}
+    pub fn nega_scout_actions(
+        &self,
+        board: &Board,
+        color: Color,
+        depth: i8,
+        scores: &Vec<(i32, (i8, i8), Board)>,
+    ) -> Vec<(i32, (i8, i8), Board)> {
+        let start = (self.stats(), Instant::now());
+        let res = Arc::new(Mutex::new(vec![]));
+        let mut alpha = -INF;
+        let beta = INF;
+        if scores.is_empty() {
+            return [].to_vec();
+        }
 
-pub fn nega_scout_actions(
-    board: &Board,
-    color: Color,
-    depth: i8,
-    scores: &Vec<(i32, (i8, i8), Board)>,
-) -> Vec<(i32, (i8, i8), Board)> {
-    let res = Arc::new(Mutex::new(vec![]));
-    let mut alpha = -INF;
-    let beta = INF;
-    if scores.is_empty() {
-        return [].to_vec();
-    }
-
-    let (first, trail) = scores.split_first().unwrap();
-    let v;
-    let fst_res = nega_scout(&first.2, board, !color, -beta, -alpha, depth, depth, false);
-    match fst_res {
-        Some(val) => v = -val,
-        None => return vec![*first],
-    }
-
-    let max = v;
-    {
-        res.lock().unwrap().push((v, first.1, first.2));
-    }
-
-    if alpha < v {
-        alpha = v;
-    }
-
-    let alpha = Arc::new(Mutex::new(alpha));
-    let max = Arc::new(Mutex::new(max));
-    let len = trail.len();
-    let mut handles = vec![];
-
-    for i in 0..THREAD_NUM {
-        let mut chunk = vec![];
-        for j in (i..len).step_by(THREAD_NUM) {
-            chunk.push(*trail.get(j).unwrap());
-        }
-        let alpha = alpha.clone();
-        let beta = beta.clone();
-        let max = max.clone();
-        let res = res.clone();
-        let board = board.clone();
-        handles.push(thread::spawn(move || {
-            for (_, action, nboard) in chunk {
-                let alpha_v;
-                {
-                    let alpha_p = alpha.lock().unwrap();
-                    alpha_v = *alpha_p;
-                }
-                let res_score = nega_scout(
-                    &nboard,
-                    &board,
-                    !color,
-                    -alpha_v - 1,
-                    -alpha_v,
-                    depth,
-                    depth,
-                    false,
-                );
+        let (first, trail) = scores.split_first().unwrap();
+        let v;
+        let mut pv = vec![];
+        let fst_res = self.nega_scout(
+            &first.2, board, !color, -beta, -alpha, depth, depth, false, &mut pv,
+        );
+        match fst_res {
+            Some(val) => v = -val,
+            None => return vec![*first],
+        }
 
-                let mut score;
-                match res_score {
-                    Some(val) => score = -val,
-                    None => break,
+        let max = v;
+        // 各手の読み筋. 最善手のものを self.pv に残す
+        let pvs = Mutex::new(vec![(first.1, pv)]);
+        {
+            res.lock().unwrap().push((v, first.1, first.2));
+        }
+
+        if alpha < v {
+            alpha = v;
+        }
+
+        let alpha = Arc::new(Mutex::new(alpha));
+        let max = Arc::new(Mutex::new(max));
+        let len = trail.len();
+
+        thread::scope(|s| {
+            let mut handles = vec![];
+            for i in 0..THREAD_NUM {
+                let mut chunk = vec![];
+                for j in (i..len).step_by(THREAD_NUM) {
+                    chunk.push(*trail.get(j).unwrap());
                 }
-                if alpha_v < score {
-                    let alpha_v;
-                    {
-                        let mut alpha_p = alpha.lock().unwrap();
-                        if *alpha_p < score {
-                            *alpha_p = score;
+                let alpha = alpha.clone();
+                let beta = beta.clone();
+                let max = max.clone();
+                let res = res.clone();
+                let board = board.clone();
+                let pvs = &pvs;
+                handles.push(s.spawn(move || {
+                    let mut pv = vec![];
+                    for (_, action, nboard) in chunk {
+                        let alpha_v;
+                        {
+                            let alpha_p = alpha.lock().unwrap();
+                            alpha_v = *alpha_p;
                         }
-                        alpha_v = *alpha_p;
-                    }
-                    let res_score = nega_scout(
-                        &nboard, &board, !color, -beta, -alpha_v, depth, depth, false,
-                    );
-                    match res_score {
-                        Some(val) => score = -val,
-                        None => break,
-                    }
-                    let mut alpha_p = alpha.lock().unwrap();
-                    if *alpha_p < score {
-                        *alpha_p = score;
+                        let res_score = self.nega_scout(
+                            &nboard,
+                            &board,
+                            !color,
+                            -alpha_v - 1,
+                            -alpha_v,
+                            depth,
+                            depth,
+                            false,
+                            &mut pv,
+                        );
+
+                        let mut score;
+                        match res_score {
+                            Some(val) => score = -val,
+                            None => break,
+                        }
+                        if alpha_v < score {
+                            let alpha_v;
+                            {
+                                let mut alpha_p = alpha.lock().unwrap();
+                                if *alpha_p < score {
+                                    *alpha_p = score;
+                                }
+                                alpha_v = *alpha_p;
+                            }
+                            let res_score = self.nega_scout(
+                                &nboard, &board, !color, -beta, -alpha_v, depth, depth, false,
+                                &mut pv,
+                            );
+                            match res_score {
+                                Some(val) => score = -val,
+                                None => break,
+                            }
+                            let mut alpha_p = alpha.lock().unwrap();
+                            if *alpha_p < score {
+                                *alpha_p = score;
+                            }
+                        }
+                        let mut max_p = max.lock().unwrap();
+                        if *max_p < score {
+                            *max_p = score;
+                        }
+                        res.lock().unwrap().push((score, action, nboard));
+                        pvs.lock().unwrap().push((action, pv.clone()));
                     }
-                }
-                let mut max_p = max.lock().unwrap();
-                if *max_p < score {
-                    *max_p = score;
-                }
-                res.lock().unwrap().push((score, action, nboard));
+                }));
             }
-        }));
-    }
-    let (tx, rx) = channel();
-    let timer = thread::spawn(move || loop {
-        thread::sleep(std::time::Duration::from_millis(10));
-        if SystemTime::now() >= unsafe { TIME_LIMIT } {
-            unsafe {
-                IS_TIMEOUT = true;
+            let (tx, rx) = channel();
+            let timer = s.spawn(move || self.watch_timeout(rx));
+            for handle in handles {
+                handle.join().unwrap();
             }
-            break;
+            tx.send(true).unwrap_or(());
+            timer.join().unwrap();
+        });
+
+        let mut res = res.clone().lock().unwrap().clone();
+
+        if res.is_empty() {
+            return vec![*first];
         }
-        match rx.try_recv() {
-            Ok(_) => break,
-            _ => continue,
+        res.sort_by(|a, b| b.0.cmp(&a.0));
+        let best = res.first().unwrap().1;
+        let mut pv = vec![Move::Mv(best.0, best.1)];
+        if let Some((_, child_pv)) = pvs.lock().unwrap().iter().find(|p| p.0 == best) {
+            pv.extend(child_pv.iter().cloned());
         }
-    });
-    for handle in handles {
-        handle.join().unwrap();
+        log!(
+            "Depth: {}, Score: {}, PV: {}",
+            depth,
+            max.clone().lock().unwrap(),
+            string_of_pv(&pv)
+        );
+        *self.pv.lock().unwrap() = pv;
+        let stats = self.stats().since(&start.0);
+        let elapsed = start.1.elapsed();
+        log!(
+            "Nodes: {}, {} ms, {} nps",
+            stats,
+            elapsed.as_millis(),
+            stats.nps(elapsed)
+        );
+        res.to_vec()
     }
-    tx.send(true).unwrap_or(());
-    timer.join().unwrap();
 
-    let mut res = res.clone().lock().unwrap().clone();
+    fn nega_scout_action(&self, board: &Board, color: Color, depths: Vec<i8>) -> (i8, i8) {
+        let mut scores = get_move_ordering_score(board, color);
+        // 有効分岐数は前の反復とのノード数の比を深さの差で割り戻して求める
+        let mut prev: Option<(i8, u64)> = None;
 
-    if res.is_empty() {
-        return vec![*first];
+        for d in depths {
+            let start = self.stats();
+            scores = self.nega_scout_actions(board, color, d, &mut scores);
+            let nodes = self.stats().since(&start).nodes();
+            if let Some((prev_d, prev_nodes)) = prev {
+                if d > prev_d && prev_nodes > 0 && !self.is_timeout.load(Ordering::Relaxed) {
+                    let ebf = (nodes as f64 / prev_nodes as f64).powf(1.0 / (d - prev_d) as f64);
+                    log!("EBF: {:.2} (depth {} -> {})", ebf, prev_d, d);
+                }
+            }
+            prev = Some((d, nodes));
+            if !scores.is_empty() && scores.first().unwrap().0 == INF {
+                log!("will win");
+                return scores.first().unwrap().1;
+            }
+            if self.is_timeout.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+
+        if scores.is_empty() {
+            (-1, -1)
+        } else {
+            scores.first().unwrap().1
+        }
     }
-    println!("Depth: {}, Score: {}", depth, max.clone().lock().unwrap());
-    res.sort_by(|a, b| b.0.cmp(&a.0));
-    res.to_vec()
 }
 
-fn nega_scout_action(board: &Board, color: Color, depths: Vec<i8>) -> (i8, i8) {
-    let mut scores = get_move_ordering_score(board, color);
+// 解析した1手. score は null window の上下限ではなく全幅で読んだ値で, pv はその手から始まる
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub action: (i8, i8),
+    pub score: i32,
+    pub depth: i8,
+    pub pv: Vec<Move>,
+}
 
-    for d in depths {
-        scores = nega_scout_actions(board, color, d, &mut scores);
-        if !scores.is_empty() && scores.first().unwrap().0 == INF {
-            println!("will win");
-            return scores.first().unwrap().1;
-        }
-        if unsafe { IS_TIMEOUT } {
-            break;
+impl Engine {
+    // 全ての合法手をそれぞれ全幅で depth 手読み, 良い順に返す. time ミリ秒で打ち切り,
+    // 時間切れで読めなかった手は含まない. 残り手数は board から数え直す
+    pub fn analyze(&mut self, board: &Board, color: Color, depth: i8, time: u64) -> Vec<Analysis> {
+        let depth = depth.max(1);
+        self.count = 68 - count(board.0 | board.1) as u8;
+        self.time_limit = SystemTime::now() + Duration::from_millis(time);
+        self.is_timeout.store(false, Ordering::Relaxed);
+        let this = &*self;
+        let scores = get_move_ordering_score(board, color);
+        let res = Mutex::new(vec![]);
+        thread::scope(|s| {
+            let mut handles = vec![];
+            for i in 0..THREAD_NUM {
+                let chunk = scores
+                    .iter()
+                    .skip(i)
+                    .step_by(THREAD_NUM)
+                    .collect::<Vec<_>>();
+                let res = &res;
+                handles.push(s.spawn(move || {
+                    for (_, action, nboard) in chunk {
+                        let mut child_pv = vec![];
+                        let score = match this.nega_scout(
+                            nboard,
+                            board,
+                            !color,
+                            -INF,
+                            INF,
+                            depth,
+                            depth,
+                            false,
+                            &mut child_pv,
+                        ) {
+                            Some(val) => -val,
+                            None => break,
+                        };
+                        let mut pv = vec![Move::Mv(action.0, action.1)];
+                        pv.append(&mut child_pv);
+                        res.lock().unwrap().push(Analysis {
+                            action: *action,
+                            score,
+                            depth,
+                            pv,
+                        });
+                    }
+                }));
+            }
+            let (tx, rx) = channel();
+            let timer = s.spawn(move || this.watch_timeout(rx));
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            tx.send(true).unwrap_or(());
+            timer.join().unwrap();
+        });
+        let mut res = res.into_inner().unwrap();
+        res.sort_by_key(|a| std::cmp::Reverse(a.score));
+        res
+    }
+
+    // 置換表の最善手をたどって読み筋を作る.
+    // 他の手の探索で上書きされていることがあるので, 合法手でなくなったところで打ち切る
+    fn tt_pv(&self, board: &Board, color: Color, len: i8) -> Vec<Move> {
+        let mut board = *board;
+        let mut color = color;
+        let mut pv = vec![];
+        while pv.len() < len as usize {
+            let moves = valid_moves(&board, color);
+            if moves.is_empty() {
+                if valid_moves(&board, !color).is_empty() {
+                    break;
+                }
+                pv.push(Move::Pass);
+                color = !color;
+                continue;
+            }
+            match self.tt.probe(hash(&board, color)).and_then(|e| e.best) {
+                Some(action) if moves.contains(&action) => {
+                    do_move(&mut board, &Move::Mv(action.0, action.1), color);
+                    pv.push(Move::Mv(action.0, action.1));
+                    color = !color;
+                }
+                _ => break,
+            }
         }
+        pv
+    }
+}
+
+pub fn string_of_pv(pv: &[Move]) -> String {
+    pv.iter()
+        .map(|mv| mv.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// 評価値を石差に直す. 終盤 (残り4手以内) の評価は石差の 33554431 倍, 終局は ±INF で勝ち負けだけなので
+// ±64 とする. 中盤の評価は石差の尺度を持たないので, 確定石1つの重みを1石として換算する
+pub fn score_to_discs(score: i32, weights: &EvalWeights) -> f64 {
+    const DISC_SCORE: i32 = 33554431;
+    let discs = match score {
+        INF => 64.0,
+        s if s == -INF => -64.0,
+        s if s % DISC_SCORE == 0 => (s / DISC_SCORE) as f64,
+        s => s as f64 / weights.stable as f64,
+    };
+    discs.clamp(-64.0, 64.0)
+}
+
+// 終局まで読めた値は勝ち負けだけを表す ±INF なので, 数字ではなく WIN/LOSS と書く
+pub fn format_score(score: i32) -> String {
+    match score {
+        INF => "WIN".to_string(),
+        s if s == -INF => "LOSS".to_string(),
+        s => s.to_string(),
     }
+}
 
-    if scores.is_empty() {
-        (-1, -1)
-    } else {
-        scores.first().unwrap().1
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+fn calc_move_ordering_value(board: &Board, color: Color) -> i32 {
+    -(count(valid_mask(board, !color)) as i32)
+}
+
+pub fn get_move_ordering_score(board: &Board, color: Color) -> Vec<(i32, (i8, i8), Board)> {
+    let valid_moves = valid_moves(board, color);
+    let mut nodes = vec![];
+    for action in valid_moves {
+        let mut n_board = board.clone();
+        do_move(&mut n_board, &Move::Mv(action.0, action.1), color);
+        nodes.push((calc_move_ordering_value(&n_board, color), action, n_board));
+    }
+    nodes.sort_by(|a, b| b.0.cmp(&a.0));
+    nodes
+}
+
 /*fn apply_joseki(board: &Board, color: Color) -> (i8, i8) {
     let valid_moves = valid_moves(board, color);
     let mut best_action = (-1, -1);
@@ -469,39 +979,117 @@ fn nega_scout_action(board: &Board, color: Color, depths: Vec<i8>) -> (i8, i8) {
 }*/
 
 use rand::prelude::*;
-fn apply_joseki(board: &Board, color: Color) -> (i8, i8) {
-    let valid_moves = valid_moves(board, color);
-    let mut actions = vec![];
-    let mut rates = vec![];
-    for action in valid_moves {
-        let mut nboard = board.clone();
+// book の局面の評価値を末端から求め直す. 末端は depth 手読みで評価する
+pub fn book_values(book: &mut Book, depth: i8) -> usize {
+    let engine = Engine::new();
+    let mut n = 0;
+    book.propagate(|board, mover| {
+        n += 1;
+        if n % 1000 == 0 {
+            log!("Evaluated {} leaves.", n);
+        }
+        engine.search_value(board, mover, depth)
+    })
+}
+
+// 合法手のうち打った後の局面が book にあるもの. accepted は filter を通ったかどうか
+#[derive(Debug, Clone)]
+pub struct JosekiCandidate {
+    pub action: (i8, i8),
+    pub wins: i32,
+    pub losses: i32,
+    pub value: Option<i32>,
+    pub symmetry: usize,
+    pub accepted: bool,
+}
+
+pub fn joseki_candidates(
+    book: &Book,
+    filter: &BookFilter,
+    board: &Board,
+    color: Color,
+) -> Vec<JosekiCandidate> {
+    let mut candidates = vec![];
+    for action in valid_moves(board, color) {
+        let mut nboard = *board;
         do_move(&mut nboard, &Move::Mv(action.0, action.1), color);
-        for b in expand(&nboard) {
-            let res = DATABASE.get(&b);
-            if !res.is_some() {
-                continue;
-            }
-            let res = res.unwrap();
-            let rate = res.0 as u32;
-            if res.0 as f32 / (res.0 + res.1) as f32 >= 0.5 {
-                println!("Found: {}", rate);
-                actions.push(action);
-                rates.push(res.0 as u32);
-            }
+        if let Some(res) = book.get(&nboard) {
+            candidates.push(JosekiCandidate {
+                action,
+                wins: res.0,
+                losses: res.1,
+                value: book.value(&nboard),
+                symmetry: canonical_with_symmetry(&nboard).1,
+                accepted: filter.accepts(&nboard, res),
+            });
         }
     }
-    if actions.is_empty() {
-        return (-1, -1);
+    candidates
+}
+
+// apply_joseki が各候補を選ぶ確率
+pub fn joseki_probabilities(candidates: &[JosekiCandidate], policy: BookPolicy) -> Vec<f64> {
+    let mut probs = vec![0.0; candidates.len()];
+    let accepted = (0..candidates.len())
+        .filter(|&i| candidates[i].accepted)
+        .collect::<Vec<usize>>();
+    if accepted.is_empty() {
+        return probs;
+    }
+    if policy == BookPolicy::Value {
+        let best = accepted
+            .iter()
+            .filter(|&&i| candidates[i].value.is_some())
+            .max_by_key(|&&i| candidates[i].value);
+        if let Some(&i) = best {
+            probs[i] = 1.0;
+            return probs;
+        }
+    }
+    let total = accepted
+        .iter()
+        .map(|&i| candidates[i].wins as f64)
+        .sum::<f64>();
+    for &i in &accepted {
+        probs[i] = if total > 0.0 {
+            candidates[i].wins as f64 / total
+        } else {
+            1.0 / accepted.len() as f64
+        };
+    }
+    probs
+}
+
+pub fn apply_joseki(
+    book: &Book,
+    filter: &BookFilter,
+    policy: BookPolicy,
+    board: &Board,
+    color: Color,
+) -> (i8, i8) {
+    let candidates = joseki_candidates(book, filter, board, color);
+    for c in candidates.iter().filter(|c| c.accepted) {
+        log!("Found: {} {:?}", c.wins, c.value);
+    }
+    let probs = joseki_probabilities(&candidates, policy);
+    let rd = random::<f64>();
+    log!("Rd: {:.3}", rd);
+    let mut sum = 0.0;
+    let mut chosen = None;
+    for (c, p) in candidates.iter().zip(&probs) {
+        if *p > 0.0 {
+            sum += p;
+            chosen = Some(c);
+            if sum > rd {
+                break;
+            }
+        }
     }
-    let rd = random::<u32>() % (rates.iter().sum::<u32>());
-    println!("Rd: {}", rd);
-    let mut sum = 0;
-    for i in 0..rates.len() {
-        sum += rates[i];
-        if sum > rd {
-            println!("Chosed: {}", rates[i]);
-            return actions[i];
+    match chosen {
+        Some(c) => {
+            log!("Chosed: {} {:?}", c.wins, c.value);
+            c.action
         }
+        None => (-1, -1),
     }
-    *actions.last().unwrap()
 }
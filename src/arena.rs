@@ -0,0 +1,219 @@
+use crate::ai::{Engine, EngineConfig};
+use crate::book::Book;
+use crate::color::Color;
+use crate::command::Move;
+use crate::play::{count, do_move, init_board, parse_move_list, valid_moves, Board};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub struct GameResult {
+    pub black_discs: i32,
+    pub white_discs: i32,
+    pub timeout: Option<Color>,
+}
+
+impl GameResult {
+    // 黒から見た勝敗 (1, 0, -1)
+    pub fn black_result(&self) -> i32 {
+        match self.timeout {
+            Some(color) => {
+                if color {
+                    -1
+                } else {
+                    1
+                }
+            }
+            None => (self.black_discs - self.white_discs).signum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArenaStats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    pub margin_sum: i32,
+}
+
+impl ArenaStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    pub fn add(&mut self, result: i32, margin: i32) {
+        match result {
+            1 => self.wins += 1,
+            -1 => self.losses += 1,
+            _ => self.draws += 1,
+        }
+        self.margin_sum += margin;
+    }
+
+    // 対局がなければ五分として扱う
+    pub fn score(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    pub fn average_margin(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        self.margin_sum as f64 / self.games() as f64
+    }
+
+    // Elo 差の推定値と 95% 信頼区間の半幅. 全局が同じ結果なら分散から幅を出せないので無限大
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, f64::INFINITY);
+        }
+        let n = self.games() as f64;
+        let p = self.score();
+        let var = (self.wins as f64 * (1.0 - p).powi(2)
+            + self.draws as f64 * (0.5 - p).powi(2)
+            + self.losses as f64 * p.powi(2))
+            / n;
+        if var == 0.0 {
+            return (elo_of_score(p), f64::INFINITY);
+        }
+        let se = (var / n).sqrt();
+        let lo = elo_of_score(p - 1.96 * se);
+        let hi = elo_of_score(p + 1.96 * se);
+        (elo_of_score(p), (hi - lo) / 2.0)
+    }
+}
+
+fn elo_of_score(p: f64) -> f64 {
+    let p = p.clamp(1e-3, 1.0 - 1e-3);
+    -400.0 * (1.0 / p - 1.0).log10()
+}
+
+pub fn load_openings(path: &str) -> Vec<Vec<Move>> {
+    let file = File::open(path).expect("file not found");
+    let reader = BufReader::new(file);
+    let mut openings = vec![];
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.unwrap();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_move_list(&line) {
+            Some(moves) => openings.push(moves),
+            None => println!("{}:{}: invalid opening: {}", path, n + 1, line),
+        }
+    }
+    openings
+}
+
+pub fn play_game(
+    black: &mut Engine,
+    white: &mut Engine,
+    opening: &[Move],
+    time: u64,
+) -> GameResult {
+    let mut board: Board = init_board();
+    let mut color = true;
+    for mv in opening {
+        if valid_moves(&board, color).is_empty() {
+            color = !color;
+        }
+        do_move(&mut board, mv, color);
+        color = !color;
+    }
+    black.init_ai_from(true, &board);
+    white.init_ai_from(false, &board);
+
+    let mut remain = [time, time];
+    let mut is_passed = false;
+    loop {
+        let moves = valid_moves(&board, color);
+        if moves.is_empty() && is_passed {
+            break;
+        }
+        let (engine, idx) = if color {
+            (&mut *black, 0)
+        } else {
+            (&mut *white, 1)
+        };
+        engine.set_time_remain(remain[idx]);
+        let start = Instant::now();
+        let mv = engine.play(&board, color, is_passed);
+        let elapsed = start.elapsed().as_millis() as u64;
+        if elapsed > remain[idx] {
+            return GameResult {
+                black_discs: count(board.0) as i32,
+                white_discs: count(board.1) as i32,
+                timeout: Some(color),
+            };
+        }
+        remain[idx] -= elapsed;
+        is_passed = matches!(mv, Move::Pass);
+        do_move(&mut board, &mv, color);
+        color = !color;
+    }
+    GameResult {
+        black_discs: count(board.0) as i32,
+        white_discs: count(board.1) as i32,
+        timeout: None,
+    }
+}
+
+// a と b で先後を入れ替えながら対戦し, a から見た成績を返す
+pub fn run(
+    a: EngineConfig,
+    b: EngineConfig,
+    book: Arc<Book>,
+    games: u32,
+    time: u64,
+    openings: &[Vec<Move>],
+) -> ArenaStats {
+    let mut engine_a = Engine::with_config(a);
+    let mut engine_b = Engine::with_config(b);
+    engine_a.set_book(book.clone());
+    engine_b.set_book(book);
+    let mut stats = ArenaStats::default();
+    for game in 0..games {
+        let opening: &[Move] = if openings.is_empty() {
+            &[]
+        } else {
+            &openings[(game / 2) as usize % openings.len()]
+        };
+        let a_is_black = game % 2 == 0;
+        let result = if a_is_black {
+            play_game(&mut engine_a, &mut engine_b, opening, time)
+        } else {
+            play_game(&mut engine_b, &mut engine_a, opening, time)
+        };
+        let (res, a_discs, b_discs) = if a_is_black {
+            (
+                result.black_result(),
+                result.black_discs,
+                result.white_discs,
+            )
+        } else {
+            (
+                -result.black_result(),
+                result.white_discs,
+                result.black_discs,
+            )
+        };
+        stats.add(res, a_discs - b_discs);
+        println!(
+            "Game {}: A ({}) {} vs. {} B -- W {} L {} D {}",
+            game + 1,
+            if a_is_black { "BLACK" } else { "WHITE" },
+            a_discs,
+            b_discs,
+            stats.wins,
+            stats.losses,
+            stats.draws
+        );
+    }
+    stats
+}
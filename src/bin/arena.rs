@@ -0,0 +1,126 @@
+use getopts::{Matches, Options};
+use reversi_rust::ai::EngineConfig;
+use reversi_rust::arena::{load_openings, run};
+use reversi_rust::book::Book;
+use reversi_rust::play::EvalWeights;
+use std::sync::Arc;
+
+fn parse_weights(str: &str) -> EvalWeights {
+    let w = str
+        .split(',')
+        .map(|v| v.trim().parse().expect("invalid weight"))
+        .collect::<Vec<i32>>();
+    if w.len() != 4 {
+        panic!("Invalid weights : {}.", str);
+    }
+    EvalWeights {
+        openness: w[0],
+        weight: w[1],
+        candidates: w[2],
+        stable: w[3],
+    }
+}
+
+fn engine_config(matches: &Matches, suffix: &str) -> EngineConfig {
+    let mut config = EngineConfig::default();
+    if let Some(depth) = matches.opt_str(&format!("depth-{}", suffix)) {
+        config.depth = depth.parse().expect("invalid depth");
+        if config.depth < 5 {
+            panic!("Invalid depth : {}.", config.depth);
+        }
+    }
+    if let Some(weights) = matches.opt_str(&format!("weights-{}", suffix)) {
+        config.weights = parse_weights(&weights);
+    }
+    if let Some(policy) = matches.opt_str(&format!("book-policy-{}", suffix)) {
+        config.book_policy = policy.parse().expect("invalid book policy");
+    }
+    if let Some(empties) = matches.opt_str(&format!("exact-empties-{}", suffix)) {
+        config.exact_empties = empties.parse().expect("invalid empties");
+    }
+    if matches.opt_present(&format!("no-book-{}", suffix)) {
+        config.use_book = false;
+    }
+    config
+}
+
+fn main() {
+    let mut opts = Options::new();
+    opts.optopt("n", "games", "number of games (default = 10)", "NUMBER");
+    opts.optopt("t", "time", "time per player in ms (default = 60000)", "MS");
+    opts.optopt("o", "openings", "file of opening move sequences", "FILE");
+    opts.optopt(
+        "b",
+        "book",
+        "opening book file (default = data/book.bin)",
+        "FILE",
+    );
+    for suffix in ["a", "b"] {
+        opts.optopt(
+            "",
+            &format!("depth-{}", suffix),
+            "midgame search depth, at least 5 (default = 10)",
+            "NUMBER",
+        );
+        opts.optopt(
+            "",
+            &format!("weights-{}", suffix),
+            "openness,weight,candidates,stable (default = 10,1,10,50)",
+            "LIST",
+        );
+        opts.optopt(
+            "",
+            &format!("book-policy-{}", suffix),
+            "frequency or value (default = frequency)",
+            "POLICY",
+        );
+        opts.optopt(
+            "",
+            &format!("exact-empties-{}", suffix),
+            "solve the exact disc margin at or below this many empties (default = 14)",
+            "NUMBER",
+        );
+        opts.optflag("", &format!("no-book-{}", suffix), "disable opening book");
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            panic!("{}", f.to_string())
+        }
+    };
+    let games = matches
+        .opt_str("n")
+        .map_or(10, |n| n.parse().expect("invalid games"));
+    let time = matches
+        .opt_str("t")
+        .map_or(60000, |t| t.parse().expect("invalid time"));
+    let openings = matches
+        .opt_str("o")
+        .map_or(vec![], |path| load_openings(&path));
+    let book_path = matches.opt_str("b").unwrap_or("data/book.bin".to_string());
+    let book = Book::load(&book_path).unwrap_or_else(|e| {
+        println!(
+            "Book: cannot load {} ({}), using search only.",
+            book_path, e
+        );
+        Book::new()
+    });
+    let a = engine_config(&matches, "a");
+    let b = engine_config(&matches, "b");
+    println!("A: {:?}", a);
+    println!("B: {:?}", b);
+
+    let stats = run(a, b, Arc::new(book), games, time, &openings);
+    let (elo, error) = stats.elo();
+    println!(
+        "A vs. B: W {} L {} D {} ({:.1}%)",
+        stats.wins,
+        stats.losses,
+        stats.draws,
+        stats.score() * 100.0
+    );
+    println!("Average margin: {:+.2}", stats.average_margin());
+    println!("Elo: {:+.1} +/- {:.1}", elo, error);
+}
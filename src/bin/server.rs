@@ -0,0 +1,238 @@
+use getopts::Options;
+use reversi_rust::color::Color;
+use reversi_rust::command::{Command, Move, Wl};
+use reversi_rust::command_parser::parse_command;
+use reversi_rust::play::{count, do_move, init_board, print_board, valid_moves, Board};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct ServerOptions {
+    port: u16,
+    time: u64,
+    games: u32,
+    verbose: bool,
+}
+
+fn parameters() -> ServerOptions {
+    let mut opts = Options::new();
+    opts.optopt("p", "port", "port number (default = 3000)", "NUMBER");
+    opts.optopt("t", "time", "time per player in ms (default = 60000)", "MS");
+    opts.optopt("g", "games", "games per pair (default = 2)", "NUMBER");
+    opts.optflag("v", "verbose", "verbose mode");
+
+    let args: Vec<String> = std::env::args().collect();
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            panic!("{}", f.to_string())
+        }
+    };
+    ServerOptions {
+        port: matches
+            .opt_str("p")
+            .map_or(3000, |p| p.parse().expect("invalid port")),
+        time: matches
+            .opt_str("t")
+            .map_or(60000, |t| t.parse().expect("invalid time")),
+        games: matches
+            .opt_str("g")
+            .map_or(2, |g| g.parse().expect("invalid games")),
+        verbose: matches.opt_present("v"),
+    }
+}
+
+struct Client {
+    name: String,
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    score: i32,
+    wins: i32,
+    loses: i32,
+}
+
+impl Client {
+    fn send(&mut self, command: &Command) {
+        println!("send to {}: {}", self.name, command);
+        writeln!(self.stream, "{}", command).unwrap_or(());
+    }
+
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        self.stream.set_read_timeout(timeout).ok()?;
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                println!("received from {}: {}", self.name, line.trim_end());
+                Some(line)
+            }
+        }
+    }
+}
+
+fn accept(stream: TcpStream) -> Option<Client> {
+    let mut client = Client {
+        name: String::new(),
+        stream: stream.try_clone().ok()?,
+        reader: BufReader::new(stream),
+        score: 0,
+        wins: 0,
+        loses: 0,
+    };
+    let line = client.receive(Some(Duration::from_secs(10)))?;
+    match parse_command(&line) {
+        Ok(Command::Open(name)) => {
+            client.name = name;
+            Some(client)
+        }
+        _ => None,
+    }
+}
+
+fn color_name(color: Color) -> &'static str {
+    if color {
+        "BLACK"
+    } else {
+        "WHITE"
+    }
+}
+
+enum Outcome {
+    Finished,
+    Forfeit(Color, &'static str),
+}
+
+// players[0] が黒, players[1] が白
+fn play_game(players: &mut [Client; 2], time: u64, verbose: bool) {
+    let names = [players[0].name.clone(), players[1].name.clone()];
+    players[0].send(&Command::Start(true, names[1].clone(), time as i32));
+    players[1].send(&Command::Start(false, names[0].clone(), time as i32));
+
+    let mut board: Board = init_board();
+    let mut remain = [time, time];
+    let mut color = true;
+    let outcome = loop {
+        let idx = if color { 0 } else { 1 };
+        let start = Instant::now();
+        let timeout = Duration::from_millis(remain[idx].max(1));
+        let line = match players[idx].receive(Some(timeout)) {
+            Some(line) => line,
+            None if start.elapsed() >= timeout => break Outcome::Forfeit(color, "TIMEOUT"),
+            None => break Outcome::Forfeit(color, "DISCONNECTED"),
+        };
+        let elapsed = start.elapsed().as_millis() as u64;
+        if elapsed > remain[idx] {
+            break Outcome::Forfeit(color, "TIMEOUT");
+        }
+        remain[idx] -= elapsed;
+
+        let mv = match parse_command(&line) {
+            Ok(Command::Move(mv)) => mv,
+            _ => break Outcome::Forfeit(color, "ILLEGAL_MOVE"),
+        };
+        let moves = valid_moves(&board, color);
+        match mv {
+            Move::GiveUp => break Outcome::Forfeit(color, "GIVEUP"),
+            Move::Pass if !moves.is_empty() => break Outcome::Forfeit(color, "ILLEGAL_MOVE"),
+            Move::Mv(i, j) if !moves.contains(&(i, j)) => {
+                break Outcome::Forfeit(color, "ILLEGAL_MOVE")
+            }
+            _ => {}
+        }
+        do_move(&mut board, &mv, color);
+        if verbose {
+            print_board(&board);
+        }
+        if valid_moves(&board, true).is_empty() && valid_moves(&board, false).is_empty() {
+            break Outcome::Finished;
+        }
+        players[idx].send(&Command::Ack(remain[idx] as i32));
+        players[1 - idx].send(&Command::Move(mv));
+        color = !color;
+    };
+
+    let discs = [count(board.0) as i32, count(board.1) as i32];
+    let (winner, reason) = match outcome {
+        Outcome::Finished if discs[0] > discs[1] => (Some(0), "DOUBLE_PASS"),
+        Outcome::Finished if discs[0] < discs[1] => (Some(1), "DOUBLE_PASS"),
+        Outcome::Finished => (None, "DOUBLE_PASS"),
+        Outcome::Forfeit(color, reason) => (Some(if color { 1 } else { 0 }), reason),
+    };
+    for idx in 0..2 {
+        let wl = match winner {
+            Some(w) if w == idx => {
+                players[idx].wins += 1;
+                Wl::Win
+            }
+            Some(_) => {
+                players[idx].loses += 1;
+                Wl::Lose
+            }
+            None => Wl::Tie,
+        };
+        players[idx].score += discs[idx] - discs[1 - idx];
+        players[idx].send(&Command::End(
+            wl,
+            discs[idx],
+            discs[1 - idx],
+            reason.to_string(),
+        ));
+    }
+    println!(
+        "{} ({}) {} vs. {} {} ({}) -- {}.",
+        names[0],
+        color_name(true),
+        discs[0],
+        discs[1],
+        names[1],
+        color_name(false),
+        reason
+    );
+}
+
+fn run_match(mut players: [Client; 2], time: u64, games: u32, verbose: bool) {
+    for _ in 0..games {
+        play_game(&mut players, time, verbose);
+        players.swap(0, 1);
+    }
+    let stat = players
+        .iter()
+        .map(|p| (p.name.clone(), (p.score, p.wins, p.loses)))
+        .collect::<Vec<_>>();
+    for player in players.iter_mut() {
+        player.send(&Command::Bye(stat.clone()));
+    }
+}
+
+fn main() {
+    let opts = parameters();
+    let listener = TcpListener::bind(("0.0.0.0", opts.port)).expect("failed to bind");
+    println!("Waiting for connections on port {}.", opts.port);
+
+    // OPEN を待つ間に他の接続を止めないよう, 挨拶はスレッドごとに受けて組み合わせ側へ渡す
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Some(client) = accept(stream) {
+                    tx.send(client).unwrap_or(());
+                }
+            });
+        }
+    });
+
+    let mut waiting: Option<Client> = None;
+    for client in rx {
+        println!("{} connected.", client.name);
+        match waiting.take() {
+            None => waiting = Some(client),
+            Some(first) => {
+                let (time, games, verbose) = (opts.time, opts.games, opts.verbose);
+                thread::spawn(move || run_match([first, client], time, games, verbose));
+            }
+        }
+    }
+}
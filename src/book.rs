@@ -0,0 +1,512 @@
+use crate::color::Color;
+use crate::command::Move;
+use crate::play::{canonical, count, do_move, init_board, valid_moves, Board};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+
+const MAGIC: &[u8; 4] = b"RVBK";
+const VERSION: u32 = 2;
+// version 2 で各局面に評価値を付けた. 値のない局面は NO_VALUE で書く
+const NO_VALUE: i32 = i32::MIN;
+
+// 定石手の選び方. Frequency は勝数に比例した乱択, Value は評価値が最大の手
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookPolicy {
+    Frequency,
+    Value,
+}
+
+impl std::str::FromStr for BookPolicy {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<BookPolicy, String> {
+        match str {
+            "frequency" => Ok(BookPolicy::Frequency),
+            "value" => Ok(BookPolicy::Value),
+            _ => Err(format!("unknown book policy {}", str)),
+        }
+    }
+}
+
+// depth 手目までの局面だけを使い, 対局数が min_games 未満か
+// 勝率が min_win_rate 未満のエントリは無視する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookFilter {
+    pub depth: usize,
+    pub min_games: i32,
+    pub min_win_rate: f32,
+}
+
+impl Default for BookFilter {
+    fn default() -> Self {
+        BookFilter {
+            depth: 20,
+            min_games: 1,
+            min_win_rate: 0.5,
+        }
+    }
+}
+
+impl BookFilter {
+    pub fn accepts(&self, board: &Board, res: &(i32, i32)) -> bool {
+        let games = res.0 + res.1;
+        ply(board) <= self.depth
+            && games > 0
+            && games >= self.min_games
+            && res.0 as f32 / games as f32 >= self.min_win_rate
+    }
+}
+
+// 石が4つより少ない局面 (NBoard で置いた盤面など) は 0 手目とみなす
+pub fn ply(board: &Board) -> usize {
+    (count(board.0 | board.1) as usize).saturating_sub(4)
+}
+
+// 盤面 (手を打った直後) ごとに, その手を打った側の (勝数, 負数) を持つ.
+// キーは canonical で正規化するので, 対称な局面の統計は1つにまとまる.
+// values は propagate で求めた, 手を打った側から見た評価値
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    entries: HashMap<Board, (i32, i32)>,
+    values: HashMap<Board, i32>,
+}
+
+impl Book {
+    pub fn new() -> Book {
+        Book::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, board: &Board) -> Option<&(i32, i32)> {
+        self.entries.get(&canonical(board))
+    }
+
+    pub fn value(&self, board: &Board) -> Option<i32> {
+        self.values.get(&canonical(board)).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Board, &(i32, i32))> {
+        self.entries.iter()
+    }
+
+    pub fn add(&mut self, board: Board, wins: i32, losses: i32) {
+        let entry = self.entries.entry(canonical(&board)).or_insert((0, 0));
+        entry.0 += wins;
+        entry.1 += losses;
+    }
+
+    // パスも含めた1局の手順を再生し, depth 手目までの局面に勝敗を加える.
+    // 不正な手があればそこで打ち切る. 加えた局面数を返す
+    pub fn learn(&mut self, moves: &[Move], winner: Color, depth: usize) -> usize {
+        let mut board = init_board();
+        let mut color = true;
+        let mut added = 0;
+        for mv in moves {
+            match mv {
+                Move::Mv(i, j) if valid_moves(&board, color).contains(&(*i, *j)) => {
+                    do_move(&mut board, mv, color);
+                    if ply(&board) > depth {
+                        break;
+                    }
+                    if color == winner {
+                        self.add(board, 1, 0);
+                    } else {
+                        self.add(board, 0, 1);
+                    }
+                    added += 1;
+                }
+                Move::Pass if valid_moves(&board, color).is_empty() => {}
+                _ => break,
+            }
+            color = !color;
+        }
+        added
+    }
+
+    pub fn retain(&mut self, filter: &BookFilter) {
+        self.entries.retain(|board, res| filter.accepts(board, res));
+        let entries = &self.entries;
+        self.values.retain(|board, _| entries.contains_key(board));
+    }
+
+    // 局面を手順の木として, 末端を leaf(盤面, 手を打った側) で評価し, 値を根へ negamax で戻す.
+    // 手番は手数の偶奇で決める (序盤のパスは考えない). 子は book にある相手の応手だけを見る.
+    // 評価した末端の数を返す
+    pub fn propagate<F: FnMut(&Board, Color) -> i32>(&mut self, mut leaf: F) -> usize {
+        let mut boards = self.entries.keys().copied().collect::<Vec<Board>>();
+        boards.sort_by_key(|board| std::cmp::Reverse(ply(board)));
+        self.values.clear();
+        let mut leaves = 0;
+        for board in boards {
+            let mover = ply(&board) % 2 == 1;
+            let best = valid_moves(&board, !mover)
+                .into_iter()
+                .filter_map(|(i, j)| {
+                    let mut nboard = board;
+                    do_move(&mut nboard, &Move::Mv(i, j), !mover);
+                    self.values.get(&canonical(&nboard)).copied()
+                })
+                .max();
+            let value = match best {
+                Some(v) => -v,
+                None => {
+                    leaves += 1;
+                    leaf(&board, mover)
+                }
+            };
+            self.values.insert(board, value);
+        }
+        leaves
+    }
+
+    fn set_value(&mut self, board: Board, value: i32) {
+        if value != NO_VALUE {
+            self.values.insert(canonical(&board), value);
+        }
+    }
+
+    // 先頭が MAGIC ならバイナリ, そうでなければテキストとして読む
+    pub fn load(path: &str) -> io::Result<Book> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if reader.fill_buf()?.starts_with(MAGIC) {
+            Book::read_binary(reader)
+        } else {
+            Book::read_text(reader)
+        }
+    }
+
+    // 一時ファイルに書いてから置き換えるので, 書き込み中に止まっても元のファイルは壊れない
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        if path.ends_with(".txt") {
+            self.write_text(&mut writer)?;
+        } else {
+            self.write_binary(&mut writer)?;
+        }
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        fs::rename(&tmp, path)
+    }
+
+    fn read_binary<R: Read>(mut reader: R) -> io::Result<Book> {
+        let mut header = [0; 16];
+        reader.read_exact(&mut header)?;
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != 1 && version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported book version {}",
+                version
+            )));
+        }
+        let len = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let mut book = Book::new();
+        let mut record = [0; 28];
+        let size = if version == 1 { 24 } else { 28 };
+        for _ in 0..len {
+            reader.read_exact(&mut record[..size])?;
+            let black = u64::from_le_bytes(record[0..8].try_into().unwrap());
+            let white = u64::from_le_bytes(record[8..16].try_into().unwrap());
+            let wins = i32::from_le_bytes(record[16..20].try_into().unwrap());
+            let losses = i32::from_le_bytes(record[20..24].try_into().unwrap());
+            book.add((black, white), wins, losses);
+            if version != 1 {
+                let value = i32::from_le_bytes(record[24..28].try_into().unwrap());
+                book.set_value((black, white), value);
+            }
+        }
+        Ok(book)
+    }
+
+    fn write_binary<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (board, (wins, losses)) in self.sorted() {
+            let value = self.values.get(&board).copied().unwrap_or(NO_VALUE);
+            writer.write_all(&board.0.to_le_bytes())?;
+            writer.write_all(&board.1.to_le_bytes())?;
+            writer.write_all(&wins.to_le_bytes())?;
+            writer.write_all(&losses.to_le_bytes())?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    // 1行に "黒 白 勝数 負数 [評価値]" を10進で書く. '#' 以降はコメント
+    fn read_text<R: BufRead>(reader: R) -> io::Result<Book> {
+        let mut book = Book::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let terms = line.split_whitespace().collect::<Vec<&str>>();
+            let (parsed, value) = match terms[..] {
+                [black, white, wins, losses] => (
+                    (black.parse(), white.parse(), wins.parse(), losses.parse()),
+                    None,
+                ),
+                [black, white, wins, losses, value] => (
+                    (black.parse(), white.parse(), wins.parse(), losses.parse()),
+                    Some(value),
+                ),
+                _ => {
+                    return Err(invalid_data(format!(
+                        "line {}: expected 4 or 5 fields",
+                        n + 1
+                    )))
+                }
+            };
+            let invalid = || invalid_data(format!("line {}: invalid number", n + 1));
+            match parsed {
+                (Ok(black), Ok(white), Ok(wins), Ok(losses)) => {
+                    book.add((black, white), wins, losses);
+                    if let Some(value) = value {
+                        book.set_value((black, white), value.parse().map_err(|_| invalid())?);
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(book)
+    }
+
+    fn write_text<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (board, (wins, losses)) in self.sorted() {
+            match self.values.get(&board) {
+                Some(value) => writeln!(
+                    writer,
+                    "{} {} {} {} {}",
+                    board.0, board.1, wins, losses, value
+                )?,
+                None => writeln!(writer, "{} {} {} {}", board.0, board.1, wins, losses)?,
+            }
+        }
+        writer.flush()
+    }
+
+    fn sorted(&self) -> Vec<(Board, (i32, i32))> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::play::expand;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("book-{}-{}", std::process::id(), name))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    // f5 d6 c3 の3局面. values を付けるかどうかを選べる
+    fn sample_book(with_values: bool) -> Book {
+        let mut book = Book::new();
+        let moves = [Move::Mv(5, 4), Move::Mv(3, 5), Move::Mv(2, 2)];
+        book.learn(&moves, true, 60);
+        book.learn(&moves[..2], false, 60);
+        if with_values {
+            let keys = book.sorted().into_iter().map(|e| e.0).collect::<Vec<_>>();
+            book.set_value(keys[0], -12);
+            book.set_value(keys[1], 7);
+        }
+        book
+    }
+
+    fn assert_same(a: &Book, b: &Book) {
+        assert_eq!(a.sorted(), b.sorted());
+        assert_eq!(a.values, b.values);
+    }
+
+    fn round_trip(book: &Book, name: &str) -> Book {
+        let path = temp_path(name);
+        book.save(&path).unwrap();
+        let read = Book::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        read
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let book = sample_book(true);
+        assert_eq!(book.len(), 3);
+        assert_same(&round_trip(&book, "v2.bin"), &book);
+        assert_same(
+            &round_trip(&sample_book(false), "v2-plain.bin"),
+            &sample_book(false),
+        );
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let book = sample_book(true);
+        assert_same(&round_trip(&book, "values.txt"), &book);
+        let book = sample_book(false);
+        assert_same(&round_trip(&book, "plain.txt"), &book);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let book = sample_book(false);
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&(book.len() as u64).to_le_bytes());
+        for (board, (wins, losses)) in book.sorted() {
+            bytes.extend_from_slice(&board.0.to_le_bytes());
+            bytes.extend_from_slice(&board.1.to_le_bytes());
+            bytes.extend_from_slice(&wins.to_le_bytes());
+            bytes.extend_from_slice(&losses.to_le_bytes());
+        }
+        let path = temp_path("v1.bin");
+        fs::write(&path, &bytes).unwrap();
+        let read = Book::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_same(&read, &book);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let err = Book::read_text("1 2 3\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: expected 4 or 5 fields");
+        let err = Book::read_text("# comment\n1 2 3 x\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid number");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&9u32.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        let err = Book::read_binary(&bytes[..]).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported book version 9");
+    }
+
+    // 対称な局面は1つのエントリにまとまり, どの向きで引いても同じ値になる
+    #[test]
+    fn symmetric_positions_merge() {
+        let mut board = init_board();
+        for (mv, color) in [(Move::Mv(5, 4), true), (Move::Mv(3, 5), false)] {
+            do_move(&mut board, &mv, color);
+        }
+        let boards = expand(&board);
+        assert_ne!(boards[1], boards[6]);
+        let mut book = Book::new();
+        book.add(boards[1], 2, 1);
+        book.add(boards[6], 3, 4);
+        assert_eq!(book.len(), 1);
+        for b in &boards {
+            assert_eq!(book.get(b), Some(&(5, 5)));
+        }
+    }
+
+    #[test]
+    fn learn_counts_both_colours() {
+        let moves = [Move::Mv(5, 4), Move::Mv(3, 5), Move::Mv(2, 2)];
+        let mut boards = vec![];
+        let mut board = init_board();
+        for (k, mv) in moves.iter().enumerate() {
+            do_move(&mut board, mv, k % 2 == 0);
+            boards.push(board);
+        }
+        let mut book = Book::new();
+        assert_eq!(book.learn(&moves, true, 60), 3);
+        assert_eq!(book.learn(&moves, false, 60), 3);
+        assert_eq!(book.learn(&moves, false, 60), 3);
+        // 黒が打った局面は黒から, 白が打った局面は白から見た勝敗
+        assert_eq!(book.get(&boards[0]), Some(&(1, 2)));
+        assert_eq!(book.get(&boards[1]), Some(&(2, 1)));
+        assert_eq!(book.get(&boards[2]), Some(&(1, 2)));
+    }
+
+    #[test]
+    fn learn_stops_at_depth_and_illegal_moves() {
+        let moves = [Move::Mv(5, 4), Move::Mv(3, 5), Move::Mv(2, 2)];
+        let mut book = Book::new();
+        assert_eq!(book.learn(&moves, true, 2), 2);
+        assert_eq!(book.len(), 2);
+
+        // c4 は f5 の後の白には打てない
+        let mut book = Book::new();
+        let moves = [Move::Mv(5, 4), Move::Mv(2, 3), Move::Mv(3, 5)];
+        assert_eq!(book.learn(&moves, true, 60), 1);
+        assert_eq!(book.len(), 1);
+        // 打てる手があるときのパスも不正
+        let mut book = Book::new();
+        assert_eq!(book.learn(&[Move::Mv(5, 4), Move::Pass], true, 60), 1);
+    }
+
+    #[test]
+    fn learn_plays_through_passes() {
+        let (_, games) = crate::wthor::load_wthor("tests/fixtures/tiny.wtb").unwrap();
+        let (moves, board) = games[1].replay().unwrap();
+        assert!(moves.contains(&Move::Pass));
+        let played = moves.iter().filter(|mv| **mv != Move::Pass).count();
+        let mut book = Book::new();
+        assert_eq!(book.learn(&moves, true, 60), played);
+        assert!(book.get(&board).is_some());
+    }
+
+    // f5 の後に d6 (続きは c3) と f4 の2つの応手がある木
+    #[test]
+    fn propagate_negamax_from_leaves() {
+        let after = |moves: &[(i8, i8)]| {
+            let mut board = init_board();
+            for (k, (i, j)) in moves.iter().enumerate() {
+                do_move(&mut board, &Move::Mv(*i, *j), k % 2 == 0);
+            }
+            board
+        };
+        let f5 = after(&[(5, 4)]);
+        let d6 = after(&[(5, 4), (3, 5)]);
+        let c3 = after(&[(5, 4), (3, 5), (2, 2)]);
+        let f4 = after(&[(5, 4), (5, 3)]);
+        let mut book = Book::new();
+        for board in [f5, d6, c3, f4] {
+            book.add(board, 1, 0);
+        }
+
+        let mut calls = vec![];
+        let leaves = book.propagate(|board, mover| {
+            calls.push((*board, mover));
+            if *board == canonical(&c3) {
+                10
+            } else {
+                4
+            }
+        });
+        assert_eq!(leaves, 2);
+        calls.sort();
+        let mut expected = vec![(canonical(&c3), true), (canonical(&f4), false)];
+        expected.sort();
+        assert_eq!(calls, expected);
+
+        // 値は手を打った側から見たもので, 1手ごとに符号が変わる
+        assert_eq!(book.value(&c3), Some(10));
+        assert_eq!(book.value(&d6), Some(-10));
+        assert_eq!(book.value(&f4), Some(4));
+        assert_eq!(book.value(&f5), Some(-4));
+    }
+}
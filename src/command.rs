@@ -1,4 +1,5 @@
 use crate::color::Color;
+use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Wl {
@@ -7,6 +8,7 @@ pub enum Wl {
     Tie = 2,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Move {
     Mv(i8, i8),
     Pass,
@@ -25,15 +27,9 @@ impl Move {
             }
         }
     }
-    pub fn clone(&self) -> Move {
-        match self {
-            Move::Pass => Move::Pass,
-            Move::GiveUp => Move::GiveUp,
-            Move::Mv(i, j) => Move::Mv(i.to_owned(), j.to_owned()),
-        }
-    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     Open(String),
     End(Wl, i32, i32, String),
@@ -41,5 +37,46 @@ pub enum Command {
     Start(Color, String, i32),
     Ack(i32),
     Bye(Vec<(String, (i32, i32, i32))>),
-    Empty,
+}
+
+impl fmt::Display for Wl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Wl::Win => write!(f, "WIN"),
+            Wl::Lose => write!(f, "LOSE"),
+            Wl::Tie => write!(f, "TIE"),
+        }
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.string_of_move())
+    }
+}
+
+// command_parser::parse_command で読み戻せる形で書き出す
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Open(name) => write!(f, "OPEN {}", name),
+            Command::End(wl, n, m, reason) => write!(f, "END {} {} {} {}", wl, n, m, reason),
+            Command::Move(mv) => write!(f, "MOVE {}", mv),
+            Command::Start(color, name, time) => write!(
+                f,
+                "START {} {} {}",
+                if *color { "BLACK" } else { "WHITE" },
+                name,
+                time
+            ),
+            Command::Ack(time) => write!(f, "ACK {}", time),
+            Command::Bye(stat) => {
+                write!(f, "BYE")?;
+                for (name, (score, wins, loses)) in stat {
+                    write!(f, " {} {} {} {}", name, score, wins, loses)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
@@ -2,86 +2,211 @@ use crate::command::Command;
 use crate::command::Move;
 use crate::command::Wl;
 use crate::Color;
+use std::fmt;
+use std::str::FromStr;
 
-fn color_from_string(str: &str) -> Color {
-    match str {
-        "BLACK" => true,
-        "WHITE" => false,
-        _ => panic!("Invalid Color : {}.", str),
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    TooManyArguments(String),
+    InvalidColor(String),
+    InvalidResult(String),
+    InvalidMove(String),
+    InvalidNumber(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::UnknownCommand(s) => write!(f, "unknown command {}", s),
+            ParseError::MissingArgument(s) => write!(f, "missing {}", s),
+            ParseError::TooManyArguments(s) => write!(f, "unexpected argument {}", s),
+            ParseError::InvalidColor(s) => write!(f, "invalid color {}", s),
+            ParseError::InvalidResult(s) => write!(f, "invalid result {}", s),
+            ParseError::InvalidMove(s) => write!(f, "invalid move {}", s),
+            ParseError::InvalidNumber(s) => write!(f, "invalid number {}", s),
+        }
     }
 }
 
-fn wl_from_string(str: &str) -> Wl {
+impl std::error::Error for ParseError {}
+
+fn color_from_string(str: &str) -> Result<Color, ParseError> {
     match str {
-        "WIN" => Wl::Win,
-        "LOSE" => Wl::Lose,
-        "TIE" => Wl::Tie,
-        _ => panic!("Invalid Color : {}.", str),
+        "BLACK" => Ok(true),
+        "WHITE" => Ok(false),
+        _ => Err(ParseError::InvalidColor(str.to_string())),
     }
 }
 
-#[derive(Debug, Clone)]
-struct PlaceError;
+impl FromStr for Wl {
+    type Err = ParseError;
 
-fn move_from_string(str: &str) -> Move {
-    match str {
-        "PASS" => Move::Pass,
-        "GIVEUP" => Move::GiveUp,
-        str => Move::Mv(
-            str.as_bytes()[0] as i8 - ('A' as i8),
-            str.as_bytes()[1] as i8 - ('1' as i8),
-        ),
+    fn from_str(str: &str) -> Result<Wl, ParseError> {
+        match str {
+            "WIN" => Ok(Wl::Win),
+            "LOSE" => Ok(Wl::Lose),
+            "TIE" => Ok(Wl::Tie),
+            _ => Err(ParseError::InvalidResult(str.to_string())),
+        }
     }
 }
 
-pub fn parse_command(line: &String) -> Command {
-    let mut tokens = line.split_whitespace();
-    match tokens.next() {
-        Some("MOVE") => {
-            if let Some(place) = tokens.next() {
-                return Command::Move(move_from_string(place));
-            }
+impl FromStr for Move {
+    type Err = ParseError;
+
+    // "A1" から "H8" までと PASS, GIVEUP だけを受け付ける
+    fn from_str(str: &str) -> Result<Move, ParseError> {
+        match str.as_bytes() {
+            b"PASS" => Ok(Move::Pass),
+            b"GIVEUP" => Ok(Move::GiveUp),
+            [i @ b'A'..=b'H', j @ b'1'..=b'8'] => Ok(Move::Mv((i - b'A') as i8, (j - b'1') as i8)),
+            _ => Err(ParseError::InvalidMove(str.to_string())),
         }
+    }
+}
+
+fn arg<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    name: &'static str,
+) -> Result<&'a str, ParseError> {
+    tokens.next().ok_or(ParseError::MissingArgument(name))
+}
+
+fn number<T: FromStr>(str: &str) -> Result<T, ParseError> {
+    str.parse()
+        .map_err(|_| ParseError::InvalidNumber(str.to_string()))
+}
+
+pub fn parse_command(line: &str) -> Result<Command, ParseError> {
+    let mut tokens = line.split_whitespace();
+    let command = match tokens.next() {
+        None => return Err(ParseError::Empty),
+        Some("OPEN") => Command::Open(arg(&mut tokens, "name")?.to_string()),
+        Some("MOVE") => Command::Move(arg(&mut tokens, "move")?.parse()?),
         Some("START") => {
-            if let (Some(wb), Some(opponent_name), Some(time)) =
-                (tokens.next(), tokens.next(), tokens.next())
-            {
-                if let Ok(time) = time.parse() {
-                    return Command::Start(color_from_string(wb), opponent_name.to_string(), time);
-                }
-            }
-        }
-        Some("ACK") => {
-            if let Some(time) = tokens.next() {
-                if let Ok(time) = time.parse() {
-                    return Command::Ack(time);
-                }
-            }
+            let color = color_from_string(arg(&mut tokens, "color")?)?;
+            let opponent_name = arg(&mut tokens, "opponent name")?.to_string();
+            let time = number(arg(&mut tokens, "time")?)?;
+            Command::Start(color, opponent_name, time)
         }
+        Some("ACK") => Command::Ack(number(arg(&mut tokens, "time")?)?),
         Some("END") => {
-            if let (Some(wl), Some(n), Some(m), Some(reason)) =
-                (tokens.next(), tokens.next(), tokens.next(), tokens.next())
-            {
-                if let (Ok(n), Ok(m)) = (n.parse(), m.parse()) {
-                    return Command::End(wl_from_string(wl), n, m, reason.to_string());
-                }
-            }
+            let wl = arg(&mut tokens, "result")?.parse()?;
+            let n = number(arg(&mut tokens, "disc count")?)?;
+            let m = number(arg(&mut tokens, "disc count")?)?;
+            let reason = arg(&mut tokens, "reason")?.to_string();
+            Command::End(wl, n, m, reason)
         }
         Some("BYE") => {
             let mut stat = Vec::new();
-            while let (Some(player), Some(score), Some(wins), Some(loses)) =
-                (tokens.next(), tokens.next(), tokens.next(), tokens.next())
-            {
-                if let (Ok(score), Ok(wins), Ok(loses)) =
-                    (score.parse(), wins.parse(), loses.parse())
-                {
-                    stat.push((player.to_string(), (score, wins, loses)));
-                }
+            while let Some(player) = tokens.next() {
+                let score = number(arg(&mut tokens, "score")?)?;
+                let wins = number(arg(&mut tokens, "wins")?)?;
+                let loses = number(arg(&mut tokens, "loses")?)?;
+                stat.push((player.to_string(), (score, wins, loses)));
             }
-            return Command::Bye(stat);
+            Command::Bye(stat)
         }
-        _ => {}
+        Some(cmd) => return Err(ParseError::UnknownCommand(cmd.to_string())),
+    };
+    match tokens.next() {
+        Some(extra) => Err(ParseError::TooManyArguments(extra.to_string())),
+        None => Ok(command),
+    }
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(str: &str) -> Result<Command, ParseError> {
+        parse_command(str)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Command::Empty
+    fn round_trip(command: Command) {
+        let line = command.to_string();
+        assert_eq!(parse_command(&line), Ok(command), "{}", line);
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        round_trip(Command::Open("Alice".to_string()));
+        round_trip(Command::End(Wl::Win, 40, 24, "DOUBLE_PASS".to_string()));
+        round_trip(Command::End(Wl::Lose, 0, 64, "GIVEUP".to_string()));
+        round_trip(Command::End(Wl::Tie, 32, 32, "DOUBLE_PASS".to_string()));
+        round_trip(Command::Move(Move::Mv(5, 4)));
+        round_trip(Command::Move(Move::Pass));
+        round_trip(Command::Move(Move::GiveUp));
+        round_trip(Command::Start(true, "Bob".to_string(), 60000));
+        round_trip(Command::Start(false, "Bob".to_string(), 60000));
+        round_trip(Command::Ack(59000));
+        round_trip(Command::Bye(vec![]));
+        round_trip(Command::Bye(vec![
+            ("Alice".to_string(), (3, 2, 1)),
+            ("Bob".to_string(), (-3, 1, 2)),
+        ]));
+    }
+
+    #[test]
+    fn moves_round_trip() {
+        for i in 0..8 {
+            for j in 0..8 {
+                let mv = Move::Mv(i, j);
+                assert_eq!(mv.to_string().parse(), Ok(mv));
+            }
+        }
+        assert_eq!("PASS".parse(), Ok(Move::Pass));
+        assert_eq!("GIVEUP".parse(), Ok(Move::GiveUp));
+        assert_eq!("F5".parse(), Ok(Move::Mv(5, 4)));
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        assert_eq!(parse_command(""), Err(ParseError::Empty));
+        assert_eq!(parse_command("   "), Err(ParseError::Empty));
+        assert_eq!(
+            parse_command("HELLO"),
+            Err(ParseError::UnknownCommand("HELLO".to_string()))
+        );
+        assert_eq!(
+            parse_command("MOVE"),
+            Err(ParseError::MissingArgument("move"))
+        );
+        assert_eq!(
+            parse_command("START BLACK Bob"),
+            Err(ParseError::MissingArgument("time"))
+        );
+        assert_eq!(
+            parse_command("ACK 100 200"),
+            Err(ParseError::TooManyArguments("200".to_string()))
+        );
+        assert_eq!(
+            parse_command("START RED Bob 100"),
+            Err(ParseError::InvalidColor("RED".to_string()))
+        );
+        assert_eq!(
+            parse_command("END DRAW 32 32 DOUBLE_PASS"),
+            Err(ParseError::InvalidResult("DRAW".to_string()))
+        );
+        assert_eq!(
+            parse_command("MOVE Z9"),
+            Err(ParseError::InvalidMove("Z9".to_string()))
+        );
+        assert_eq!(
+            parse_command("MOVE a1"),
+            Err(ParseError::InvalidMove("a1".to_string()))
+        );
+        assert_eq!(
+            parse_command("ACK soon"),
+            Err(ParseError::InvalidNumber("soon".to_string()))
+        );
+    }
 }
@@ -0,0 +1,395 @@
+use crate::color::Color;
+use crate::command::Move;
+use crate::play::{do_move, init_board, valid_moves, Board};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 1局分の記録. moves は黒番から順に, パスも含めて交互に並ぶ.
+// 時間はすべてミリ秒
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub black: String,
+    pub white: String,
+    pub time: u64,
+    pub moves: Vec<(Move, u64)>,
+    pub black_discs: i32,
+    pub white_discs: i32,
+    pub winner: Option<Color>,
+    pub reason: String,
+    pub black_remain: u64,
+    pub white_remain: u64,
+    pub date: SystemTime,
+}
+
+fn clock(ms: u64) -> String {
+    let sec = ms / 1000;
+    format!("{}:{:02}", sec / 60, sec % 60)
+}
+
+// GGF の DT 形式 (UTC)
+fn date(time: SystemTime) -> String {
+    let sec = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let days = (sec / 86400) as i64;
+    // 1970-01-01 からの日数を年月日に直す
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!(
+        "{}.{:02}.{:02}_{:02}:{:02}:{:02}.UTC",
+        y,
+        m,
+        d,
+        sec % 86400 / 3600,
+        sec % 3600 / 60,
+        sec % 60
+    )
+}
+
+fn escape(str: &str) -> String {
+    str.replace('\\', "\\\\").replace(']', "\\]")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GgfError {
+    Unterminated,
+    InvalidTag(String),
+    InvalidBoard(String),
+    InvalidMove(usize, String),
+    IllegalMove(usize, String),
+}
+
+impl fmt::Display for GgfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GgfError::Unterminated => write!(f, "unterminated game record"),
+            GgfError::InvalidTag(t) => write!(f, "invalid tag {}", t),
+            GgfError::InvalidBoard(b) => write!(f, "invalid board {}", b),
+            GgfError::InvalidMove(n, mv) => write!(f, "move {}: cannot parse {}", n, mv),
+            GgfError::IllegalMove(n, mv) => write!(f, "move {}: {} is illegal", n, mv),
+        }
+    }
+}
+
+// GGF の1局. tags は BO, B, W 以外のタグを出てきた順に持つ.
+// moves の2つ目は手の後ろの "/評価値/時間" をそのまま持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct GgfGame {
+    pub tags: Vec<(String, String)>,
+    pub board: Board,
+    pub color: Color,
+    pub moves: Vec<(Color, Move, String)>,
+}
+
+impl GgfGame {
+    pub fn new(board: Board, color: Color) -> GgfGame {
+        GgfGame {
+            tags: vec![],
+            board,
+            color,
+            moves: vec![],
+        }
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // RE の石差 (黒から見た値). ":r" などの付記は無視する
+    pub fn score(&self) -> Option<f64> {
+        self.tag("RE")?.split(':').next()?.trim().parse().ok()
+    }
+
+    pub fn winner(&self) -> Option<Color> {
+        let score = self.score()?;
+        if score > 0.0 {
+            Some(true)
+        } else if score < 0.0 {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_standard_start(&self) -> bool {
+        self.board == init_board() && self.color
+    }
+
+    // 開始局面から手順を再生し, 終局図を返す. 手番と合わない手があれば Err
+    pub fn replay(&self) -> Result<Board, GgfError> {
+        let mut board = self.board;
+        let mut color = self.color;
+        for (n, (mover, mv, _)) in self.moves.iter().enumerate() {
+            let moves = valid_moves(&board, color);
+            let legal = *mover == color
+                && match mv {
+                    Move::Mv(i, j) => moves.contains(&(*i, *j)),
+                    Move::Pass => moves.is_empty(),
+                    Move::GiveUp => false,
+                };
+            if !legal {
+                return Err(GgfError::IllegalMove(n + 1, mv.to_string()));
+            }
+            do_move(&mut board, mv, color);
+            color = !color;
+        }
+        Ok(board)
+    }
+
+    pub fn to_ggf(&self) -> String {
+        let mut str = "(;".to_string();
+        for (key, value) in &self.tags {
+            str += &format!("{}[{}]", key, escape(value));
+        }
+        str += &format!("BO[{}]", board_to_ggf(&self.board, self.color));
+        for (color, mv, rest) in &self.moves {
+            let sq = match mv {
+                Move::Pass => "PA".to_string(),
+                mv => mv.string_of_move(),
+            };
+            str += &format!("{}[{}{}]", if *color { "B" } else { "W" }, sq, rest);
+        }
+        str += ";)";
+        str
+    }
+}
+
+// "8 " の後に A1, B1, ..., H8 の順で 64 マス (-: 空, *: 黒, O: 白), 最後に手番
+fn board_to_ggf(board: &Board, color: Color) -> String {
+    let mut str = "8 ".to_string();
+    for k in 0..64 {
+        let bit = 1u64 << ((k % 8) * 8 + k / 8);
+        str.push(if board.0 & bit != 0 {
+            '*'
+        } else if board.1 & bit != 0 {
+            'O'
+        } else {
+            '-'
+        });
+    }
+    str += if color { " *" } else { " O" };
+    str
+}
+
+fn board_from_ggf(str: &str) -> Result<(Board, Color), GgfError> {
+    match str.trim_start().strip_prefix('8') {
+        Some(squares) => board_from_squares(squares),
+        None => Err(GgfError::InvalidBoard(str.to_string())),
+    }
+}
+
+// BO の "8 " より後. 64 マスと手番を読む. 空白は無視し, 黒は X, 白は o でもよい
+pub fn board_from_squares(str: &str) -> Result<(Board, Color), GgfError> {
+    let invalid = || GgfError::InvalidBoard(str.to_string());
+    let squares = str
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<char>>();
+    if squares.len() != 65 {
+        return Err(invalid());
+    }
+    let mut board = (0, 0);
+    for (k, c) in squares[..64].iter().enumerate() {
+        let bit = 1u64 << ((k % 8) * 8 + k / 8);
+        match c {
+            '*' | 'X' | 'x' => board.0 |= bit,
+            'O' | 'o' => board.1 |= bit,
+            '-' | '.' => {}
+            _ => return Err(invalid()),
+        }
+    }
+    let color = match squares[64] {
+        '*' | 'X' | 'x' => true,
+        'O' | 'o' => false,
+        _ => return Err(invalid()),
+    };
+    Ok((board, color))
+}
+
+pub fn move_from_ggf(n: usize, str: &str) -> Result<(Move, String), GgfError> {
+    let (sq, rest) = match str.find('/') {
+        Some(k) => (&str[..k], &str[k..]),
+        None => (str, ""),
+    };
+    let sq = sq.trim().to_ascii_uppercase();
+    let mv = match sq.as_str() {
+        "PA" | "PASS" => Move::Pass,
+        sq => match sq.parse::<Move>() {
+            Ok(Move::Mv(i, j)) => Move::Mv(i, j),
+            _ => return Err(GgfError::InvalidMove(n, str.to_string())),
+        },
+    };
+    Ok((mv, rest.to_string()))
+}
+
+// "(;" から ";)" までの対局をすべて読む. 対局の外の文字は読み飛ばす
+pub fn parse_ggf(text: &str) -> Vec<Result<GgfGame, GgfError>> {
+    let mut games = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("(;") {
+        rest = &rest[start + 2..];
+        let (game, next) = parse_game(rest);
+        games.push(game);
+        match next {
+            Some(next) => rest = next,
+            None => break,
+        }
+    }
+    games
+}
+
+// 1局分を読み, 続きの文字列を返す
+fn parse_game(text: &str) -> (Result<GgfGame, GgfError>, Option<&str>) {
+    let mut game = GgfGame::new(init_board(), true);
+    let mut chars = text.char_indices().peekable();
+    loop {
+        while chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+        let (start, c) = match chars.next() {
+            Some(x) => x,
+            None => return (Err(GgfError::Unterminated), None),
+        };
+        if c == ';' {
+            if let Some((k, ')')) = chars.peek().copied() {
+                return (Ok(game), Some(&text[k + 1..]));
+            }
+            return (Err(GgfError::InvalidTag(";".to_string())), skip_game(text));
+        }
+        let mut key = c.to_string();
+        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric()) {
+            key.push(c);
+        }
+        if chars.next().map(|(_, c)| c) != Some('[') {
+            let end = text[start..].find('[').map_or(text.len(), |k| start + k);
+            return (
+                Err(GgfError::InvalidTag(text[start..end].to_string())),
+                skip_game(text),
+            );
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '\\')) => {
+                    if let Some((_, c)) = chars.next() {
+                        value.push(c);
+                    }
+                }
+                Some((_, ']')) => break,
+                Some((_, c)) => value.push(c),
+                None => return (Err(GgfError::Unterminated), None),
+            }
+        }
+        let result = match key.as_str() {
+            "BO" => board_from_ggf(&value).map(|(board, color)| {
+                game.board = board;
+                game.color = color;
+            }),
+            "B" | "W" => move_from_ggf(game.moves.len() + 1, &value)
+                .map(|(mv, rest)| game.moves.push((key == "B", mv, rest))),
+            _ => {
+                game.tags.push((key, value));
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            return (Err(e), skip_game(text));
+        }
+    }
+}
+
+fn skip_game(text: &str) -> Option<&str> {
+    text.find(";)").map(|k| &text[k + 2..])
+}
+
+pub fn load_ggf(path: &str) -> io::Result<Vec<Result<GgfGame, GgfError>>> {
+    Ok(parse_ggf(&fs::read_to_string(path)?))
+}
+
+impl GameRecord {
+    // 黒から見た石差. 反則などで終わった局は勝った側が全部取ったことにする
+    pub fn result(&self) -> String {
+        let diff = self.black_discs - self.white_discs;
+        match self.reason.as_str() {
+            "DOUBLE_PASS" => format!("{:+}", diff),
+            reason => {
+                let score = match self.winner {
+                    Some(true) => 64,
+                    Some(false) => -64,
+                    None => 0,
+                };
+                let kind = if reason == "TIMEOUT" { "t" } else { "r" };
+                format!("{:+}:{}", score, kind)
+            }
+        }
+    }
+
+    // 標準のタグの後に, サーバーが返した終局理由と残り時間を独自タグで付ける
+    pub fn to_game(&self) -> GgfGame {
+        let mut game = GgfGame::new(init_board(), true);
+        let tags = [
+            ("GM", "Othello".to_string()),
+            ("PC", "reversi-rust".to_string()),
+            ("DT", date(self.date)),
+            ("PB", self.black.clone()),
+            ("PW", self.white.clone()),
+            ("RE", self.result()),
+            ("TI", clock(self.time)),
+            ("TY", "8".to_string()),
+            ("XREASON", self.reason.clone()),
+            (
+                "XDISCS",
+                format!("{} {}", self.black_discs, self.white_discs),
+            ),
+            (
+                "XREMAIN",
+                format!("{} {}", self.black_remain, self.white_remain),
+            ),
+        ];
+        game.tags = tags.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        let mut color = true;
+        for (mv, ms) in &self.moves {
+            let rest = format!("//{}.{:03}", ms / 1000, ms % 1000);
+            game.moves.push((color, mv.clone(), rest));
+            color = !color;
+        }
+        game
+    }
+
+    pub fn to_ggf(&self) -> String {
+        self.to_game().to_ggf()
+    }
+
+    // dir/<時刻>_<黒>_<白>.ggf に書き出し, そのパスを返す
+    pub fn save(&self, dir: &str) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let ms = self
+            .date
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let name = |s: &str| {
+            s.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+        };
+        let path = Path::new(dir).join(format!(
+            "{}_{}_{}.ggf",
+            ms,
+            name(&self.black),
+            name(&self.white)
+        ));
+        fs::write(&path, self.to_ggf() + "\n")?;
+        Ok(path)
+    }
+}
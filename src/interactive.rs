@@ -0,0 +1,295 @@
+use crate::ai::{format_score, log_to_stderr, string_of_pv, Engine};
+use crate::color::Color;
+use crate::command::Move;
+use crate::play::{count, do_move, get_result, init_board, print_board, valid_moves, Board};
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+fn color_name(color: Color) -> &'static str {
+    if color {
+        "Black (X)"
+    } else {
+        "White (O)"
+    }
+}
+
+// undo で戻るために, 人間の手番が来るたびに局面と残り時間を積んでおく
+struct Snapshot {
+    board: Board,
+    moves: usize,
+    remain: [u64; 2],
+}
+
+pub struct Interactive {
+    engine: Engine,
+    human: Color,
+    board: Board,
+    color: Color,
+    moves: Vec<Move>,
+    // [黒, 白] の残り時間 (ms)
+    remain: [u64; 2],
+    history: Vec<Snapshot>,
+}
+
+enum Input {
+    Play(Move),
+    Undo,
+    Hint,
+    Quit,
+}
+
+impl Interactive {
+    pub fn new(engine: Engine, human: Color, time: u64) -> Interactive {
+        Interactive {
+            engine,
+            human,
+            board: init_board(),
+            color: true,
+            moves: vec![],
+            remain: [time, time],
+            history: vec![],
+        }
+    }
+
+    fn idx(color: Color) -> usize {
+        if color {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        valid_moves(&self.board, true).is_empty() && valid_moves(&self.board, false).is_empty()
+    }
+
+    fn play(&mut self, mv: Move) {
+        do_move(&mut self.board, &mv, self.color);
+        self.moves.push(mv);
+        self.color = !self.color;
+    }
+
+    fn print_status(&self) {
+        print_board(&self.board);
+        println!(
+            "Black {} - {} White   Clock: Black {:.1}s, White {:.1}s",
+            count(self.board.0),
+            count(self.board.1),
+            self.remain[0] as f64 / 1000.0,
+            self.remain[1] as f64 / 1000.0
+        );
+    }
+
+    fn read_input<R: BufRead>(&self, input: &mut R) -> io::Result<Input> {
+        let moves = valid_moves(&self.board, self.color);
+        loop {
+            print!(
+                "{} to move (e.g. {}, undo, hint, quit)> ",
+                color_name(self.color),
+                Move::Mv(moves[0].0, moves[0].1)
+            );
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(Input::Quit);
+            }
+            let line = line.trim().to_ascii_uppercase();
+            match line.as_str() {
+                "UNDO" | "U" => return Ok(Input::Undo),
+                "HINT" | "H" => return Ok(Input::Hint),
+                "QUIT" | "Q" => return Ok(Input::Quit),
+                _ => {}
+            }
+            match line.parse::<Move>() {
+                Ok(Move::Mv(i, j)) if moves.contains(&(i, j)) => {
+                    return Ok(Input::Play(Move::Mv(i, j)))
+                }
+                _ => println!("Illegal move: {}", line),
+            }
+        }
+    }
+
+    fn hint(&mut self) {
+        let time = self.remain[Interactive::idx(self.color)];
+        let depth = self.engine.depth();
+        for a in self
+            .engine
+            .analyze(&self.board, self.color, depth, time)
+            .iter()
+            .take(3)
+        {
+            println!(
+                "Hint: {} ({}) {}",
+                Move::Mv(a.action.0, a.action.1),
+                format_score(a.score),
+                string_of_pv(&a.pv)
+            );
+        }
+    }
+
+    fn undo(&mut self) {
+        // 今の手番の局面も積まれているので, その1つ前まで戻す
+        if self.history.len() < 2 {
+            println!("Nothing to undo.");
+            return;
+        }
+        self.history.pop();
+        let snapshot = self.history.pop().unwrap();
+        self.board = snapshot.board;
+        self.color = self.human;
+        self.moves.truncate(snapshot.moves);
+        self.remain = snapshot.remain;
+    }
+
+    // 人間の手番. 時間切れになったら false を返す
+    fn human_turn<R: BufRead>(&mut self, input: &mut R) -> io::Result<Option<bool>> {
+        self.history.push(Snapshot {
+            board: self.board,
+            moves: self.moves.len(),
+            remain: self.remain,
+        });
+        let idx = Interactive::idx(self.color);
+        let start = Instant::now();
+        loop {
+            match self.read_input(input)? {
+                Input::Quit => return Ok(None),
+                Input::Hint => self.hint(),
+                Input::Undo => {
+                    self.undo();
+                    return Ok(Some(true));
+                }
+                Input::Play(mv) => {
+                    let elapsed = start.elapsed().as_millis() as u64;
+                    if elapsed > self.remain[idx] {
+                        self.remain[idx] = 0;
+                        return Ok(Some(false));
+                    }
+                    self.remain[idx] -= elapsed;
+                    self.play(mv);
+                    return Ok(Some(true));
+                }
+            }
+        }
+    }
+
+    fn engine_turn(&mut self) -> bool {
+        let idx = Interactive::idx(self.color);
+        self.engine.init_ai_from(self.color, &self.board);
+        self.engine.set_time_remain(self.remain[idx]);
+        let start = Instant::now();
+        let mv = self.engine.play(&self.board, self.color, false);
+        let elapsed = start.elapsed().as_millis() as u64;
+        if elapsed > self.remain[idx] {
+            self.remain[idx] = 0;
+            return false;
+        }
+        self.remain[idx] -= elapsed;
+        println!("Engine plays {}.", mv);
+        self.play(mv);
+        true
+    }
+
+    pub fn run<R: BufRead>(&mut self, mut input: R) -> io::Result<()> {
+        // 探索のログは標準エラー出力に回し, 盤面表示と混ざらないようにする
+        log_to_stderr(true);
+        println!("You play {}.", color_name(self.human));
+        let mut timeout = None;
+        while !self.is_over() {
+            self.print_status();
+            if valid_moves(&self.board, self.color).is_empty() {
+                println!("{} must pass.", color_name(self.color));
+                self.play(Move::Pass);
+                continue;
+            }
+            let in_time = if self.color == self.human {
+                match self.human_turn(&mut input)? {
+                    Some(in_time) => in_time,
+                    None => {
+                        println!("Quit.");
+                        return Ok(());
+                    }
+                }
+            } else {
+                self.engine_turn()
+            };
+            if !in_time {
+                timeout = Some(self.color);
+                break;
+            }
+        }
+        self.summary(timeout);
+        Ok(())
+    }
+
+    fn summary(&self, timeout: Option<Color>) {
+        self.print_status();
+        let (human, engine) = if self.human {
+            (count(self.board.0), count(self.board.1))
+        } else {
+            (count(self.board.1), count(self.board.0))
+        };
+        let result = match timeout {
+            Some(color) if color == self.human => -1,
+            Some(_) => 1,
+            None => get_result(&self.board, self.human),
+        };
+        let reason = if timeout.is_some() {
+            "timeout"
+        } else {
+            "game over"
+        };
+        match result {
+            1 => println!("You win! ({} vs. {}) -- {}.", human, engine, reason),
+            -1 => println!("You lose! ({} vs. {}) -- {}.", human, engine, reason),
+            _ => println!("Draw ({} vs. {}) -- {}.", human, engine, reason),
+        }
+        let moves = self
+            .moves
+            .iter()
+            .map(|mv| match mv {
+                Move::Pass => "PA".to_string(),
+                mv => mv.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join("");
+        println!("Moves: {}", moves);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::EngineConfig;
+
+    fn run(script: &str) -> Interactive {
+        let engine = Engine::with_config(EngineConfig {
+            depth: 5,
+            ..EngineConfig::default()
+        });
+        let mut game = Interactive::new(engine, true, 600000);
+        game.run(script.as_bytes()).unwrap();
+        game
+    }
+
+    #[test]
+    fn plays_scripted_moves() {
+        // 不正な手と打てない手は読み飛ばす
+        let game = run("z9\na1\nf5\nquit\n");
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.moves[0], Move::Mv(5, 4));
+        let mut board = init_board();
+        do_move(&mut board, &game.moves[0], true);
+        do_move(&mut board, &game.moves[1], false);
+        assert_eq!(game.board, board);
+        assert!(game.color);
+    }
+
+    #[test]
+    fn undo_returns_to_the_human_turn() {
+        let game = run("undo\nf5\nundo\nquit\n");
+        assert_eq!(game.board, init_board());
+        assert!(game.moves.is_empty());
+        assert!(game.color);
+        assert_eq!(game.remain, [600000, 600000]);
+    }
+}
@@ -0,0 +1,28 @@
+//! オセロの bitboard, 探索, 定石ブックをまとめたライブラリ.
+//! `main.rs` の TCP クライアントはこの上に乗る薄いバイナリである.
+
+pub mod ai;
+pub mod arena;
+pub mod book;
+pub mod color;
+pub mod command;
+pub mod command_parser;
+pub mod ggf;
+pub mod interactive;
+pub mod nboard;
+pub mod parse;
+pub mod play;
+pub mod query;
+mod tt;
+pub mod wthor;
+
+pub use crate::ai::{
+    apply_joseki, get_move_ordering_score, Engine, EngineConfig, SearchStats, SolveMode,
+};
+pub use crate::book::Book;
+pub use crate::color::Color;
+pub use crate::command::{Command, Move, Wl};
+pub use crate::play::{
+    canonical, do_move, evaluate, expand, get_disc_diff, get_result, init_board, valid_mask,
+    valid_moves, Board, EvalWeights,
+};
@@ -1,31 +1,55 @@
-use std::io::Read;
-use std::net::{TcpStream, ToSocketAddrs};
-mod ai;
-mod color;
-mod command;
-mod command_parser;
-mod database;
-mod parse;
-mod play;
-use crate::ai::*;
-use crate::color::Color;
-use crate::command::Command;
-use crate::command::Move;
-use crate::command::Wl;
-use crate::command_parser::parse_command;
-use crate::parse::*;
-use crate::play::*;
 use getopts::Options;
+use reversi_rust::ai::{
+    book_values, joseki_candidates, joseki_probabilities, string_of_pv, Engine, EngineConfig,
+};
+use reversi_rust::book::{Book, BookFilter, BookPolicy};
+use reversi_rust::color::Color;
+use reversi_rust::command::Command;
+use reversi_rust::command::Move;
+use reversi_rust::command::Wl;
+use reversi_rust::command_parser::parse_command;
+use reversi_rust::ggf::GameRecord;
+use reversi_rust::interactive::Interactive;
+use reversi_rust::nboard::NBoard;
+use reversi_rust::parse::create_database;
+use reversi_rust::play::{do_move, init_board, print_board, valid_mask, Board};
+use reversi_rust::query::{analysis_table, book_table, parse_position};
+use std::io::Read;
+use std::io::{self, BufWriter, Write};
 use std::io::{BufRead, BufReader};
-use std::io::{BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use once_cell::sync::Lazy;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 static OPT_HOST: Lazy<RwLock<String>> = Lazy::new(|| "localhost".to_string().into());
 static OPT_PORT: Lazy<RwLock<u16>> = Lazy::new(|| 3000.into());
 static OPT_PLAYER_NAME: Lazy<RwLock<String>> = Lazy::new(|| "Anon,".to_string().into());
 static OPT_VERBOSE: Lazy<RwLock<bool>> = Lazy::new(|| false.into());
+static OPT_BOOK: Lazy<RwLock<String>> = Lazy::new(|| "data/book.bin".to_string().into());
+static OPT_LOG_DIR: Lazy<RwLock<String>> = Lazy::new(|| "logs".to_string().into());
+static OPT_BOOK_POLICY: Lazy<RwLock<BookPolicy>> = Lazy::new(|| BookPolicy::Frequency.into());
+static OPT_BOOK_EVAL_DEPTH: Lazy<RwLock<i8>> = Lazy::new(|| 6.into());
+static OPT_PROPAGATE: Lazy<RwLock<bool>> = Lazy::new(|| false.into());
+static OPT_WTHOR: Lazy<RwLock<Vec<String>>> = Lazy::new(|| vec![].into());
+static OPT_GGF: Lazy<RwLock<Vec<String>>> = Lazy::new(|| vec![].into());
+static OPT_NBOARD: Lazy<RwLock<bool>> = Lazy::new(|| false.into());
+static OPT_QUERY: Lazy<RwLock<Option<String>>> = Lazy::new(|| None.into());
+static OPT_ANALYZE: Lazy<RwLock<Option<String>>> = Lazy::new(|| None.into());
+static OPT_DEPTH: Lazy<RwLock<i8>> = Lazy::new(|| EngineConfig::default().depth.into());
+static OPT_EXACT_EMPTIES: Lazy<RwLock<u8>> =
+    Lazy::new(|| EngineConfig::default().exact_empties.into());
+static OPT_INTERACTIVE: Lazy<RwLock<Option<Color>>> = Lazy::new(|| None.into());
+static OPT_CLOCK: Lazy<RwLock<u64>> = Lazy::new(|| 600000.into());
+static OPT_LEARN: Lazy<RwLock<bool>> = Lazy::new(|| true.into());
+static OPT_RETRIES: Lazy<RwLock<u32>> = Lazy::new(|| 10.into());
+static OPT_RETRY_WAIT: Lazy<RwLock<u64>> = Lazy::new(|| 1000.into());
+static OPT_TIMEOUT: Lazy<RwLock<u64>> = Lazy::new(|| 600.into());
+static OPT_BOOK_DEPTH: Lazy<RwLock<usize>> = Lazy::new(|| 20.into());
+static OPT_BOOK_MIN_GAMES: Lazy<RwLock<i32>> = Lazy::new(|| 1.into());
+static OPT_BOOK_MIN_RATE: Lazy<RwLock<Option<f32>>> = Lazy::new(|| None.into());
 
 static mut PARSE_MODE: bool = false;
 
@@ -34,8 +58,127 @@ fn parameters() {
     opts.optopt("H", "host", "host name (default = localhost)", "NAME");
     opts.optopt("p", "port", "port number (default = 3000)", "NUMBER");
     opts.optopt("n", "player_name", "player name (default = Anon.)", "NAME");
+    opts.optopt(
+        "",
+        "book-policy",
+        "frequency or value (default = frequency)",
+        "POLICY",
+    );
+    opts.optopt(
+        "",
+        "book-eval-depth",
+        "search depth for book leaf values (default = 6)",
+        "NUMBER",
+    );
+    opts.optmulti(
+        "",
+        "wthor",
+        "also import a WTHOR (.wtb) file with -P (repeatable)",
+        "FILE",
+    );
+    opts.optmulti(
+        "",
+        "ggf",
+        "also import a GGF file with -P (repeatable)",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "nboard",
+        "speak the NBoard engine protocol on stdin/stdout",
+    );
+    opts.optopt(
+        "Q",
+        "query",
+        "list book continuations of moves (f5d6...) or a board (64 of -XO, then X/O to move)",
+        "POSITION",
+    );
+    opts.optopt(
+        "A",
+        "analyze",
+        "score every legal move of a position (same format as --query) with its PV",
+        "POSITION",
+    );
+    opts.optopt(
+        "d",
+        "depth",
+        "search depth, at least 5 (default = 10)",
+        "NUMBER",
+    );
+    opts.optopt(
+        "",
+        "exact-empties",
+        "solve the exact disc margin at or below this many empties, WLD above (default = 14)",
+        "NUMBER",
+    );
+    opts.optopt(
+        "I",
+        "interactive",
+        "play against the engine on the terminal as black or white",
+        "COLOR",
+    );
+    opts.optopt(
+        "",
+        "clock",
+        "time per side in interactive mode (ms, default = 600000)",
+        "MS",
+    );
+    opts.optflag(
+        "",
+        "propagate",
+        "recompute the values of the book file and exit",
+    );
+    opts.optflag("", "no-learn", "do not add finished games to the book file");
+    opts.optopt(
+        "",
+        "log-dir",
+        "directory for GGF game records, empty to disable (default = logs)",
+        "DIR",
+    );
+    opts.optopt(
+        "",
+        "retries",
+        "reconnect attempts after a lost connection (default = 10)",
+        "NUMBER",
+    );
+    opts.optopt(
+        "",
+        "retry-wait",
+        "first reconnect wait in ms, doubled each time (default = 1000)",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "timeout",
+        "socket timeout in seconds on top of the opponent's clock, 0 for none (default = 600)",
+        "SEC",
+    );
     opts.optflagopt("v", "verbose", "verbose mode", "BOOL");
     opts.optflagopt("P", "parse", "database parse mode", "BOOL");
+    opts.optopt(
+        "b",
+        "book",
+        "opening book file (default = data/book.bin)",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "book-depth",
+        "use book positions up to this many moves (default = 20)",
+        "NUMBER",
+    );
+    opts.optopt(
+        "",
+        "book-min-games",
+        "ignore book positions with fewer games (default = 1)",
+        "NUMBER",
+    );
+    opts.optopt(
+        "",
+        "book-min-rate",
+        "ignore book positions with a lower win rate (default = 0.5, 0 when parsing)",
+        "RATE",
+    );
 
     let args: Vec<String> = std::env::args().collect();
     let matches = match opts.parse(&args[1..]) {
@@ -57,11 +200,108 @@ fn parameters() {
     if matches.opt_present("v") {
         *OPT_VERBOSE.try_write().unwrap() = true;
     }
+    if matches.opt_present("b") {
+        *OPT_BOOK.try_write().unwrap() = matches.opt_str("b").unwrap().to_owned();
+    }
+    if matches.opt_present("book-policy") {
+        *OPT_BOOK_POLICY.try_write().unwrap() =
+            matches.opt_str("book-policy").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("book-eval-depth") {
+        *OPT_BOOK_EVAL_DEPTH.try_write().unwrap() =
+            matches.opt_str("book-eval-depth").unwrap().parse().unwrap();
+    }
+    *OPT_WTHOR.try_write().unwrap() = matches.opt_strs("wthor");
+    *OPT_GGF.try_write().unwrap() = matches.opt_strs("ggf");
+    if matches.opt_present("nboard") {
+        *OPT_NBOARD.try_write().unwrap() = true;
+    }
+    if matches.opt_present("Q") {
+        *OPT_QUERY.try_write().unwrap() = matches.opt_str("Q");
+    }
+    if matches.opt_present("A") {
+        *OPT_ANALYZE.try_write().unwrap() = matches.opt_str("A");
+    }
+    if matches.opt_present("d") {
+        let depth: i8 = matches.opt_str("d").unwrap().parse().unwrap();
+        if depth < 5 {
+            panic!("Invalid depth : {}.", depth);
+        }
+        *OPT_DEPTH.try_write().unwrap() = depth;
+    }
+    if matches.opt_present("exact-empties") {
+        *OPT_EXACT_EMPTIES.try_write().unwrap() =
+            matches.opt_str("exact-empties").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("I") {
+        let color = match matches.opt_str("I").unwrap().to_ascii_lowercase().as_str() {
+            "black" | "b" | "x" => true,
+            "white" | "w" | "o" => false,
+            c => panic!("invalid color: {}", c),
+        };
+        *OPT_INTERACTIVE.try_write().unwrap() = Some(color);
+    }
+    if matches.opt_present("clock") {
+        *OPT_CLOCK.try_write().unwrap() = matches.opt_str("clock").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("propagate") {
+        *OPT_PROPAGATE.try_write().unwrap() = true;
+    }
+    if matches.opt_present("no-learn") {
+        *OPT_LEARN.try_write().unwrap() = false;
+    }
+    if matches.opt_present("log-dir") {
+        *OPT_LOG_DIR.try_write().unwrap() = matches.opt_str("log-dir").unwrap();
+    }
+    if matches.opt_present("retries") {
+        *OPT_RETRIES.try_write().unwrap() = matches.opt_str("retries").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("retry-wait") {
+        *OPT_RETRY_WAIT.try_write().unwrap() =
+            matches.opt_str("retry-wait").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("timeout") {
+        *OPT_TIMEOUT.try_write().unwrap() = matches.opt_str("timeout").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("book-depth") {
+        *OPT_BOOK_DEPTH.try_write().unwrap() =
+            matches.opt_str("book-depth").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("book-min-games") {
+        *OPT_BOOK_MIN_GAMES.try_write().unwrap() =
+            matches.opt_str("book-min-games").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("book-min-rate") {
+        *OPT_BOOK_MIN_RATE.try_write().unwrap() =
+            Some(matches.opt_str("book-min-rate").unwrap().parse().unwrap());
+    }
     if matches.opt_present("P") {
         unsafe { PARSE_MODE = true };
     }
 }
 
+fn engine_config() -> EngineConfig {
+    EngineConfig {
+        depth: *OPT_DEPTH.try_read().unwrap(),
+        exact_empties: *OPT_EXACT_EMPTIES.try_read().unwrap(),
+        book_filter: book_filter(BookFilter::default().min_win_rate),
+        book_policy: *OPT_BOOK_POLICY.try_read().unwrap(),
+        ..EngineConfig::default()
+    }
+}
+
+// 定石作成時は勝率で削らず, 対局時は 0.5 以上の手だけを使う
+fn book_filter(default_rate: f32) -> BookFilter {
+    BookFilter {
+        depth: *OPT_BOOK_DEPTH.try_read().unwrap(),
+        min_games: *OPT_BOOK_MIN_GAMES.try_read().unwrap(),
+        min_win_rate: OPT_BOOK_MIN_RATE
+            .try_read()
+            .unwrap()
+            .unwrap_or(default_rate),
+    }
+}
+
 enum OpMove {
     PMove(Move),
     OMove(Move),
@@ -117,123 +357,220 @@ fn print_scores(scores: &Scores) {
     println!("{}", string_of_scores(scores));
 }
 
-fn input_command(ic: &mut BufReader<TcpStream>, stream: &TcpStream) -> Command {
+// 読めない行は理由を表示して読み飛ばす
+fn input_command(ic: &mut BufReader<TcpStream>, stream: &TcpStream) -> io::Result<Command> {
     ic.chain(stream);
-    let mut line = String::new();
-    ic.read_line(&mut line).unwrap();
-    println!("Received: {}", line);
-    parse_command(&line)
+    loop {
+        let mut line = String::new();
+        if ic.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by server",
+            ));
+        }
+        println!("Received: {}", line);
+        match parse_command(&line) {
+            Ok(command) => return Ok(command),
+            Err(e) => println!("Ignored: {}", e),
+        }
+    }
+}
+
+struct Game {
+    board: Board,
+    color: Color,
+    hist: Hist,
+    oname: String,
+    is_passed: bool,
+    time: u64,
+    remain: u64,
+    op_remain: u64,
+    // 手番が始まった時刻と, hist の各手に掛かった時間 (ms)
+    clock: Instant,
+    times: Vec<u64>,
+    desynced: bool,
 }
 
-fn wait_start(ic: &mut BufReader<TcpStream>, stream: &TcpStream) {
+enum State {
+    WaitingStart,
+    MyTurn(Game),
+    OpponentTurn(Game),
+    Resigned(Game),
+    GameOver(Game, Wl, i32, i32, String),
+}
+
+// 受信したコマンドで状態を進める. 1つの接続で何局でも同じスタックのまま対局できる
+// BYE を受け取ったら Ok を返す. 接続が切れたときは Err. START を受け取ったら started を立てる
+fn run_client(
+    ic: &mut BufReader<TcpStream>,
+    stream: &TcpStream,
+    engine: &mut Engine,
+    started: &mut bool,
+) -> io::Result<()> {
+    let mut state = State::WaitingStart;
     loop {
-        let command = input_command(ic, stream);
-        match command {
-            Command::Bye(scores) => {
-                print_scores(&scores);
-                break;
-            }
-            Command::Start(color, oname, time) => {
-                let mut board = init_board();
-                set_time_remain(time as u64);
-                init_ai(color);
-                if color {
-                    my_move(ic, stream, &mut board, color, &mut vec![], &oname, false);
-                } else {
-                    op_move(ic, stream, &mut board, color, &mut vec![], &oname);
+        // 対局の組み合わせ待ちには時間制限を掛けず, 相手の手番は相手の持ち時間の分だけ待つ
+        let wait = match &state {
+            State::WaitingStart => None,
+            State::OpponentTurn(game) => Some(Duration::from_millis(game.op_remain)),
+            _ => Some(Duration::ZERO),
+        };
+        set_read_timeout(stream, wait)?;
+        state = match state {
+            State::WaitingStart => match input_command(ic, stream)? {
+                Command::Bye(scores) => {
+                    print_scores(&scores);
+                    return Ok(());
+                }
+                Command::Start(color, oname, time) => {
+                    *started = true;
+                    engine.init_ai(color);
+                    engine.set_time_remain(time as u64);
+                    let game = Game {
+                        board: init_board(),
+                        color,
+                        hist: vec![],
+                        oname,
+                        is_passed: false,
+                        time: time as u64,
+                        remain: time as u64,
+                        op_remain: time as u64,
+                        clock: Instant::now(),
+                        times: vec![],
+                        desynced: false,
+                    };
+                    if color {
+                        State::MyTurn(game)
+                    } else {
+                        State::OpponentTurn(game)
+                    }
+                }
+                command => {
+                    println!("Ignored while waiting for START: {}", command);
+                    State::WaitingStart
+                }
+            },
+            State::MyTurn(game) => my_move(ic, stream, engine, game)?,
+            State::OpponentTurn(game) => op_move(ic, stream, game)?,
+            State::Resigned(mut game) => match input_command(ic, stream)? {
+                Command::End(wl, n, m, r) => {
+                    // 食い違った手順は book に入れない
+                    game.desynced = true;
+                    State::GameOver(game, wl, n, m, r)
                 }
-                break;
+                command => {
+                    println!("Ignored after desync: {}", command);
+                    State::Resigned(game)
+                }
+            },
+            State::GameOver(game, wl, n, m, r) => {
+                proc_end(&game, wl, n, m, &r);
+                learn_book(engine, &game, wl);
+                State::WaitingStart
             }
-            _ => panic!("Invalid Command"),
         }
     }
 }
 
-fn output_command(stream: &TcpStream, command: &Command) {
+fn output_command(stream: &TcpStream, command: &Command) -> io::Result<()> {
     let mut tcp_writer = BufWriter::new(stream);
-    match command {
-        Command::Move(mv) => {
-            println!("send: MOVE {}", mv.string_of_move());
-            writeln!(tcp_writer, "MOVE {}", mv.string_of_move()).unwrap();
-        }
-        Command::Open(s) => {
-            writeln!(tcp_writer, "OPEN {}", s).unwrap();
-        }
-        _ => panic!("Invalid Command"),
-    }
+    println!("send: {}", command);
+    writeln!(tcp_writer, "{}", command)?;
+    tcp_writer.flush()
 }
 
 fn my_move(
     ic: &mut BufReader<TcpStream>,
     stream: &TcpStream,
-    board: &mut Board,
-    color: Color,
-    hist: &mut Hist,
-    oname: &str,
-    is_passed: bool,
-) {
-    let pmove = play(board, color, is_passed);
-    {
-        let _ = output_command(stream, &Command::Move(pmove.clone()));
-    }
+    engine: &mut Engine,
+    mut game: Game,
+) -> io::Result<State> {
+    let color = game.color;
+    let pmove = engine.play(&game.board, color, game.is_passed);
+    output_command(stream, &Command::Move(pmove.clone()))?;
+    game.hist.push(OpMove::PMove(pmove.clone()));
+    game.times.push(game.clock.elapsed().as_millis() as u64);
     if *OPT_VERBOSE.try_read().unwrap() {
         println!(
             "--------------------------------------------------------------------------------"
         );
-        println!("PMove: {} {:?}", pmove.string_of_move(), color);
-        print_board(board);
+        println!(
+            "PMove: {} {:?} PV: {}",
+            pmove.string_of_move(),
+            color,
+            string_of_pv(&engine.pv())
+        );
+        print_board(&game.board);
     }
 
-    do_move(board, &pmove, color);
-    let command = input_command(ic, stream);
-    match command {
+    do_move(&mut game.board, &pmove, color);
+    match input_command(ic, stream)? {
         Command::Ack(mytime) => {
-            hist.push(OpMove::PMove(pmove));
-            set_time_remain(mytime as u64);
-            op_move(ic, stream, board, color, hist, oname);
+            game.remain = mytime as u64;
+            game.clock = Instant::now();
+            engine.set_time_remain(mytime as u64);
+            Ok(State::OpponentTurn(game))
+        }
+        Command::End(wl, n, m, r) => Ok(State::GameOver(game, wl, n, m, r)),
+        command => {
+            report_desync(&game, &format!("unexpected {} after our move", command));
+            resign(stream, game)
         }
-        Command::End(wl, n, m, r) => proc_end(ic, stream, board, color, hist, oname, wl, n, m, &r),
-        _ => panic!("Invalid Command"),
     }
 }
 
-fn op_move(
-    ic: &mut BufReader<TcpStream>,
-    stream: &TcpStream,
-    board: &mut Board,
-    color: Color,
-    hist: &mut Hist,
-    oname: &str,
-) {
-    let command = input_command(ic, stream);
-    match command {
+// 相手の手が合法か. PASS は相手に打てる手がないときだけ認める
+fn is_legal_move(board: &Board, color: Color, mv: &Move) -> bool {
+    let mask = valid_mask(board, color);
+    match mv {
+        Move::Mv(i, j) => mask & (1 << (i * 8 + j)) != 0,
+        Move::Pass => mask == 0,
+        Move::GiveUp => false,
+    }
+}
+
+fn report_desync(game: &Game, reason: &str) {
+    println!("Desync: {}.", reason);
+    println!(
+        "Color: {}  Opponentname: {}.",
+        if game.color { "BLACK" } else { "WHITE" },
+        game.oname
+    );
+    print_board(&game.board);
+    print_hist(&game.hist);
+}
+
+fn op_move(ic: &mut BufReader<TcpStream>, stream: &TcpStream, mut game: Game) -> io::Result<State> {
+    match input_command(ic, stream)? {
         Command::Move(omove) => {
-            do_move(board, &omove, !color);
-            let is_passed;
-            match omove {
-                Move::Pass => is_passed = true,
-                _ => is_passed = false,
+            if !is_legal_move(&game.board, !game.color, &omove) {
+                report_desync(&game, &format!("opponent move {} is illegal", omove));
+                return resign(stream, game);
             }
-            hist.push(OpMove::OMove(omove));
-            my_move(ic, stream, board, color, hist, oname, is_passed)
+            let elapsed = game.clock.elapsed().as_millis() as u64;
+            game.op_remain = game.op_remain.saturating_sub(elapsed);
+            game.clock = Instant::now();
+            do_move(&mut game.board, &omove, !game.color);
+            game.is_passed = matches!(omove, Move::Pass);
+            game.hist.push(OpMove::OMove(omove));
+            game.times.push(elapsed);
+            Ok(State::MyTurn(game))
+        }
+        Command::End(wl, n, m, r) => Ok(State::GameOver(game, wl, n, m, r)),
+        command => {
+            report_desync(&game, &format!("unexpected {} on opponent's turn", command));
+            resign(stream, game)
         }
-        Command::End(wl, n, m, r) => proc_end(ic, stream, board, color, hist, oname, wl, n, m, &r),
-        _ => panic!("Invalid Command"),
     }
 }
 
-fn proc_end(
-    ic: &mut BufReader<TcpStream>,
-    stream: &TcpStream,
-    board: &mut Board,
-    color: Color,
-    hist: &mut Hist,
-    oname: &str,
-    wl: Wl,
-    n: i32,
-    m: i32,
-    r: &str,
-) {
+// 盤面や手番が食い違ったまま打ち続けないよう, 投了して END を待つ
+fn resign(stream: &TcpStream, game: Game) -> io::Result<State> {
+    output_command(stream, &Command::Move(Move::GiveUp))?;
+    Ok(State::Resigned(game))
+}
+
+fn proc_end(game: &Game, wl: Wl, n: i32, m: i32, r: &str) {
     match wl {
         Wl::Win => println!("You win! ({} vs. {}) -- {}.", n, m, r),
         Wl::Lose => println!("You lose! ({} vs. {}) -- {}.", n, m, r),
@@ -242,37 +579,307 @@ fn proc_end(
     println!(
         "Your name: {} ({})  Opponentname: {} ({}).",
         OPT_PLAYER_NAME.try_read().unwrap(),
-        color,
-        oname,
-        !color
+        game.color,
+        game.oname,
+        !game.color
     );
-    print_board(board);
-    print_hist(hist);
+    print_board(&game.board);
+    print_hist(&game.hist);
+    save_record(game, wl, n, m, r);
+}
 
-    wait_start(ic, stream);
+fn save_record(game: &Game, wl: Wl, n: i32, m: i32, r: &str) {
+    let dir = OPT_LOG_DIR.try_read().unwrap();
+    if dir.is_empty() {
+        return;
+    }
+    let name = OPT_PLAYER_NAME.try_read().unwrap().to_string();
+    let winner = match wl {
+        Wl::Win => Some(game.color),
+        Wl::Lose => Some(!game.color),
+        Wl::Tie => None,
+    };
+    let moves = game
+        .hist
+        .iter()
+        .zip(&game.times)
+        .map(|(opmove, ms)| match opmove {
+            OpMove::PMove(mv) | OpMove::OMove(mv) => (mv.clone(), *ms),
+        })
+        .collect();
+    let (black, white) = if game.color {
+        (name, game.oname.clone())
+    } else {
+        (game.oname.clone(), name)
+    };
+    let (black_discs, white_discs) = if game.color { (n, m) } else { (m, n) };
+    let (black_remain, white_remain) = if game.color {
+        (game.remain, game.op_remain)
+    } else {
+        (game.op_remain, game.remain)
+    };
+    let record = GameRecord {
+        black,
+        white,
+        time: game.time,
+        moves,
+        black_discs,
+        white_discs,
+        winner,
+        reason: r.to_string(),
+        black_remain,
+        white_remain,
+        date: SystemTime::now(),
+    };
+    match record.save(&dir) {
+        Ok(path) => println!("Saved game record to {}.", path.display()),
+        Err(e) => println!("Cannot save game record to {} ({}).", dir, e),
+    }
 }
 
-fn client(host: &str, port: u16) {
-    let addr = format!("{}:{}", host, port)
-        .to_socket_addrs()
-        .unwrap()
+// 終局した対局の手順と勝敗を book に加え, -b のファイルに書き戻す
+fn learn_book(engine: &mut Engine, game: &Game, wl: Wl) {
+    if !*OPT_LEARN.try_read().unwrap() || game.desynced {
+        return;
+    }
+    let winner = match wl {
+        Wl::Win => game.color,
+        Wl::Lose => !game.color,
+        Wl::Tie => return,
+    };
+    let moves = game
+        .hist
+        .iter()
+        .map(|opmove| match opmove {
+            OpMove::PMove(mv) | OpMove::OMove(mv) => mv.clone(),
+        })
+        .collect::<Vec<Move>>();
+    let depth = *OPT_BOOK_DEPTH.try_read().unwrap();
+    let added = engine.book_mut().learn(&moves, winner, depth);
+    let path = OPT_BOOK.try_read().unwrap();
+    match engine.book().save(&path) {
+        Ok(()) => println!("Book: learned {} positions, saved to {}.", added, path),
+        Err(e) => println!("Book: cannot save {} ({}).", path, e),
+    }
+}
+
+fn query_book(position: &str) {
+    let (board, color) = match parse_position(position) {
+        Some(p) => p,
+        None => {
+            println!("Invalid position : {}.", position);
+            return;
+        }
+    };
+    let book = load_book(&OPT_BOOK.try_read().unwrap()).unwrap_or_default();
+    let filter = book_filter(BookFilter::default().min_win_rate);
+    let policy = *OPT_BOOK_POLICY.try_read().unwrap();
+    print_board(&board);
+    println!("To move: {}", if color { "BLACK" } else { "WHITE" });
+    println!("Filter: {:?}, policy: {:?}", filter, policy);
+
+    let candidates = joseki_candidates(&book, &filter, &board, color);
+    if candidates.is_empty() {
+        println!("No book continuations.");
+        return;
+    }
+    let probs = joseki_probabilities(&candidates, policy);
+    for line in book_table(&candidates, &probs) {
+        println!("{}", line);
+    }
+}
+
+// 解析は時間で打ち切らない
+const ANALYZE_TIME: u64 = 24 * 60 * 60 * 1000;
+
+fn analyze(position: &str) {
+    let (board, color) = match parse_position(position) {
+        Some(p) => p,
+        None => {
+            println!("Invalid position : {}.", position);
+            return;
+        }
+    };
+    let mut engine = Engine::with_config(engine_config());
+    print_board(&board);
+    println!("To move: {}", if color { "BLACK" } else { "WHITE" });
+    let depth = engine.depth();
+    let start = Instant::now();
+    let res = engine.analyze(&board, color, depth, ANALYZE_TIME);
+    let elapsed = start.elapsed();
+    if res.is_empty() {
+        println!("No legal moves.");
+        return;
+    }
+    for line in analysis_table(&res) {
+        println!("{}", line);
+    }
+    let stats = engine.stats();
+    println!(
+        "{}, {} ms, {} nps",
+        stats,
+        elapsed.as_millis(),
+        stats.nps(elapsed)
+    );
+}
+
+// 標準出力はプロトコル専用なので, ここでのメッセージは標準エラー出力に書く
+fn nboard() {
+    let path = OPT_BOOK.try_read().unwrap().to_string();
+    let (book, loaded) = match Book::load(&path) {
+        Ok(book) => (book, true),
+        Err(e) => {
+            eprintln!("Book: cannot load {} ({}), using search only.", path, e);
+            (Book::new(), false)
+        }
+    };
+    let mut engine = Engine::with_config(engine_config());
+    engine.set_book(Arc::new(book));
+    let learn = loaded && *OPT_LEARN.try_read().unwrap();
+    let mut nboard = NBoard::new(engine, if learn { Some(path) } else { None });
+    let stdin = io::stdin();
+    if let Err(e) = nboard.run(stdin.lock(), io::stdout()) {
+        eprintln!("NBoard: {}", e);
+    }
+}
+
+fn interactive(human: Color) {
+    let book = load_book(&OPT_BOOK.try_read().unwrap()).unwrap_or_default();
+    let mut engine = Engine::with_config(engine_config());
+    engine.set_book(Arc::new(book));
+    let mut game = Interactive::new(engine, human, *OPT_CLOCK.try_read().unwrap());
+    let stdin = io::stdin();
+    if let Err(e) = game.run(stdin.lock()) {
+        println!("Interactive: {}", e);
+    }
+}
+
+fn propagate(path: &str) {
+    let mut book = Book::load(path).expect("cannot load book");
+    let leaves = book_values(&mut book, *OPT_BOOK_EVAL_DEPTH.try_read().unwrap());
+    book.save(path).expect("failed writing book");
+    println!(
+        "Propagated values from {} leaves over {} positions to {}.",
+        leaves,
+        book.len(),
+        path
+    );
+}
+
+// 読めなかったときは None. 空の book で元のファイルを上書きしないよう, 呼び出し側で学習を止める
+fn load_book(path: &str) -> Option<Book> {
+    match Book::load(path) {
+        Ok(book) => {
+            println!("Book: {} positions from {}.", book.len(), path);
+            Some(book)
+        }
+        Err(e) => {
+            println!("Book: cannot load {} ({}), using search only.", path, e);
+            None
+        }
+    }
+}
+
+// 読み込みの timeout は wait に --timeout の余裕を足したもの. wait が None なら待ち続ける
+fn set_read_timeout(stream: &TcpStream, wait: Option<Duration>) -> io::Result<()> {
+    let timeout = *OPT_TIMEOUT.try_read().unwrap();
+    if timeout == 0 {
+        return Ok(());
+    }
+    stream.set_read_timeout(wait.map(|wait| wait + Duration::from_secs(timeout)))
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let addr = (host, port)
+        .to_socket_addrs()?
         .next()
-        .unwrap();
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
     println!("Connecting to {} {}.", host, port);
-    let stream = TcpStream::connect(addr).unwrap();
+    let stream = TcpStream::connect(addr)?;
+    let timeout = *OPT_TIMEOUT.try_read().unwrap();
+    if timeout > 0 {
+        stream.set_write_timeout(Some(Duration::from_secs(timeout)))?;
+    }
     println!("Connection Ok.");
     output_command(
         &stream,
         &Command::Open(OPT_PLAYER_NAME.try_read().unwrap().to_string()),
-    );
-    let mut ic = BufReader::new(stream.try_clone().unwrap());
-    wait_start(&mut ic, &stream);
+    )?;
+    Ok(stream)
+}
+
+// 接続が切れたら待ち時間を倍にしながら (最大 60 秒) 繋ぎ直す.
+// 対局中に切れた場合, その対局は捨てて次の START から始める
+fn client(host: &str, port: u16) {
+    let mut engine = Engine::with_config(engine_config());
+    match load_book(&OPT_BOOK.try_read().unwrap()) {
+        Some(book) => engine.set_book(Arc::new(book)),
+        None => *OPT_LEARN.try_write().unwrap() = false,
+    }
+    let retries = *OPT_RETRIES.try_read().unwrap();
+    let mut failures = 0;
+    let mut wait = Duration::from_millis(*OPT_RETRY_WAIT.try_read().unwrap());
+    loop {
+        let mut started = false;
+        let res = connect(host, port).and_then(|stream| {
+            let mut ic = BufReader::new(stream.try_clone()?);
+            run_client(&mut ic, &stream, &mut engine, &mut started)
+        });
+        match res {
+            Ok(()) => return,
+            Err(e) => println!("Connection lost: {}.", e),
+        }
+        // 接続できてもすぐ切られる場合は数えたまま. 対局が始まったら数え直す
+        if started {
+            failures = 0;
+            wait = Duration::from_millis(*OPT_RETRY_WAIT.try_read().unwrap());
+        }
+        failures += 1;
+        if failures > retries {
+            println!("Giving up after {} retries.", retries);
+            return;
+        }
+        println!(
+            "Reconnecting in {} ms ({}/{}).",
+            wait.as_millis(),
+            failures,
+            retries
+        );
+        thread::sleep(wait);
+        wait = (wait * 2).min(Duration::from_secs(60));
+    }
 }
 
 fn main() {
     parameters();
     if unsafe { PARSE_MODE } {
-        create_database();
+        create_database(
+            &OPT_BOOK.try_read().unwrap(),
+            &book_filter(0.0),
+            *OPT_BOOK_EVAL_DEPTH.try_read().unwrap(),
+            &OPT_WTHOR.try_read().unwrap(),
+            &OPT_GGF.try_read().unwrap(),
+        );
+        return;
+    }
+    if *OPT_NBOARD.try_read().unwrap() {
+        nboard();
+        return;
+    }
+    if let Some(position) = OPT_ANALYZE.try_read().unwrap().as_deref() {
+        analyze(position);
+        return;
+    }
+    if let Some(human) = *OPT_INTERACTIVE.try_read().unwrap() {
+        interactive(human);
+        return;
+    }
+    if let Some(position) = OPT_QUERY.try_read().unwrap().as_deref() {
+        query_book(position);
+        return;
+    }
+    if *OPT_PROPAGATE.try_read().unwrap() {
+        propagate(&OPT_BOOK.try_read().unwrap());
         return;
     }
     let (host, port) = (OPT_HOST.try_read().unwrap(), OPT_PORT.try_read().unwrap());
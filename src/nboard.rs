@@ -0,0 +1,259 @@
+use crate::ai::{log_to_stderr, score_to_discs, Engine};
+use crate::color::Color;
+use crate::command::Move;
+use crate::ggf::{move_from_ggf, parse_ggf, GgfGame};
+use crate::play::{count, do_move, init_board, valid_moves, Board};
+use std::io::{self, BufRead, Write};
+
+// NBoard には持ち時間がないので, 1手ごとにこれだけ残っているものとして読む
+const TIME_REMAIN: u64 = 600000;
+
+// NBoard のエンジンプロトコル. GUI から標準入力でコマンドを受け取り, 標準出力に返す.
+// 探索のログは標準エラー出力に回す
+pub struct NBoard {
+    engine: Engine,
+    game: GgfGame,
+    board: Board,
+    color: Color,
+    // learn で対局を加えた book の書き出し先
+    book_path: Option<String>,
+}
+
+impl NBoard {
+    pub fn new(engine: Engine, book_path: Option<String>) -> NBoard {
+        NBoard {
+            engine,
+            game: GgfGame::new(init_board(), true),
+            board: init_board(),
+            color: true,
+            book_path,
+        }
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        log_to_stderr(true);
+        for line in input.lines() {
+            let line = line?;
+            eprintln!("nboard< {}", line);
+            for reply in self.handle(line.trim()) {
+                eprintln!("nboard> {}", reply);
+                writeln!(output, "{}", reply)?;
+            }
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    // 1行のコマンドを処理し, GUI へ返す行を返す
+    pub fn handle(&mut self, line: &str) -> Vec<String> {
+        let (cmd, arg) = match line.split_once(' ') {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
+        };
+        match cmd {
+            "nboard" => vec!["set myname reversi-rust".to_string()],
+            "ping" => vec![format!("pong {}", arg)],
+            "set" => self.set(arg),
+            "move" => self.apply_move(arg),
+            "go" => self.go(),
+            "hint" => self.hint(arg.parse().unwrap_or(1)),
+            "learn" => self.learn(),
+            "" => vec![],
+            _ => vec![format!("status unknown command {}", cmd)],
+        }
+    }
+
+    fn set(&mut self, arg: &str) -> Vec<String> {
+        let (key, value) = arg.split_once(' ').unwrap_or((arg, ""));
+        match key {
+            "depth" => match value.trim().parse() {
+                Ok(depth) => {
+                    self.engine.set_depth(depth);
+                    vec![]
+                }
+                Err(_) => vec![format!("status invalid depth {}", value)],
+            },
+            "game" => match parse_ggf(value).into_iter().next() {
+                Some(Ok(game)) => match game.replay() {
+                    Ok(board) => {
+                        let moves = game.moves.len();
+                        self.game = game;
+                        self.board = board;
+                        self.color = if moves == 0 {
+                            self.game.color
+                        } else {
+                            !self.game.moves[moves - 1].0
+                        };
+                        vec![]
+                    }
+                    Err(e) => vec![format!("status invalid game ({})", e)],
+                },
+                Some(Err(e)) => vec![format!("status invalid game ({})", e)],
+                None => vec!["status invalid game".to_string()],
+            },
+            // contempt などは使わない
+            _ => vec![],
+        }
+    }
+
+    fn apply_move(&mut self, arg: &str) -> Vec<String> {
+        let mv = match move_from_ggf(self.game.moves.len() + 1, arg) {
+            Ok((mv, _)) => mv,
+            Err(e) => return vec![format!("status {}", e)],
+        };
+        let moves = valid_moves(&self.board, self.color);
+        let legal = match mv {
+            Move::Mv(i, j) => moves.contains(&(i, j)),
+            _ => moves.is_empty(),
+        };
+        if !legal {
+            return vec![format!("status illegal move {}", arg)];
+        }
+        do_move(&mut self.board, &mv, self.color);
+        self.game.moves.push((self.color, mv, String::new()));
+        self.color = !self.color;
+        vec![]
+    }
+
+    fn go(&mut self) -> Vec<String> {
+        self.engine.init_ai_from(self.color, &self.board);
+        self.engine.set_time_remain(TIME_REMAIN);
+        let mv = self.engine.play(&self.board, self.color, false);
+        let sq = match mv {
+            Move::Mv(_, _) => mv.to_string(),
+            _ => "PA".to_string(),
+        };
+        vec![format!("=== {}", sq)]
+    }
+
+    // 上位 n 手を全幅で読んだ評価値を, NBoard が表示する石差に直して返す
+    fn hint(&mut self, n: usize) -> Vec<String> {
+        if valid_moves(&self.board, self.color).is_empty() {
+            return vec!["search PA 0 0 0".to_string()];
+        }
+        let empties = 64 - count(self.board.0 | self.board.1);
+        let depth = self.engine.depth().min(empties);
+        let weights = *self.engine.weights();
+        self.engine
+            .analyze(&self.board, self.color, depth, TIME_REMAIN)
+            .into_iter()
+            .take(n)
+            .map(|a| {
+                format!(
+                    "search {} {:.2} 0 {}",
+                    Move::Mv(a.action.0, a.action.1),
+                    score_to_discs(a.score, &weights),
+                    depth
+                )
+            })
+            .collect()
+    }
+
+    // 初期局面から終局まで打たれた対局を book に加える
+    fn learn(&mut self) -> Vec<String> {
+        let finished =
+            valid_moves(&self.board, true).is_empty() && valid_moves(&self.board, false).is_empty();
+        if !self.game.is_standard_start() || !finished {
+            return vec!["learned".to_string()];
+        }
+        let diff = count(self.board.0) - count(self.board.1);
+        if diff != 0 {
+            let moves = self
+                .game
+                .moves
+                .iter()
+                .map(|m| m.1.clone())
+                .collect::<Vec<Move>>();
+            let depth = self.engine.book_filter().depth;
+            self.engine.book_mut().learn(&moves, diff > 0, depth);
+            if let Some(path) = &self.book_path {
+                if let Err(e) = self.engine.book().save(path) {
+                    eprintln!("Book: cannot save {} ({}).", path, e);
+                }
+            }
+        }
+        vec!["learned".to_string()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nboard() -> NBoard {
+        let mut nboard = NBoard::new(Engine::new(), None);
+        assert!(nboard.handle("set depth 5").is_empty());
+        nboard
+    }
+
+    fn parse_square(sq: &str) -> (i8, i8) {
+        match sq.parse() {
+            Ok(Move::Mv(i, j)) => (i, j),
+            _ => panic!("not a square: {}", sq),
+        }
+    }
+
+    #[test]
+    fn answers_ping_and_name() {
+        let mut nboard = nboard();
+        assert_eq!(nboard.handle("nboard 2"), vec!["set myname reversi-rust"]);
+        assert_eq!(nboard.handle("ping 7"), vec!["pong 7"]);
+        assert_eq!(nboard.handle(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn set_game_then_move() {
+        let mut nboard = nboard();
+        assert!(nboard.handle("set game (;GM[Othello]B[f5];)").is_empty());
+        assert!(!nboard.color);
+        assert!(nboard.handle("move d6").is_empty());
+        assert!(nboard.color);
+        let mut board = init_board();
+        do_move(&mut board, &Move::Mv(5, 4), true);
+        do_move(&mut board, &Move::Mv(3, 5), false);
+        assert_eq!(nboard.board, board);
+        assert_eq!(nboard.game.moves.len(), 2);
+    }
+
+    #[test]
+    fn rejects_illegal_moves() {
+        let mut nboard = nboard();
+        assert!(nboard.handle("set game (;GM[Othello]B[f5];)").is_empty());
+        assert_eq!(nboard.handle("move c4"), vec!["status illegal move c4"]);
+        assert_eq!(nboard.handle("move pa"), vec!["status illegal move pa"]);
+        assert!(nboard.handle("move z9")[0].starts_with("status "));
+        assert_eq!(nboard.game.moves.len(), 1);
+    }
+
+    #[test]
+    fn go_plays_a_legal_square_or_passes() {
+        let mut nboard = nboard();
+        let reply = nboard.handle("go");
+        assert_eq!(reply.len(), 1);
+        let sq = reply[0].strip_prefix("=== ").unwrap();
+        assert!(valid_moves(&init_board(), true).contains(&parse_square(sq)));
+
+        // 黒は a1 の白を挟めないのでパスするしかない
+        let bo = format!("8 OX{} *", "-".repeat(62));
+        assert!(nboard
+            .handle(&format!("set game (;GM[Othello]BO[{}];)", bo))
+            .is_empty());
+        assert_eq!(nboard.handle("go"), vec!["=== PA"]);
+    }
+
+    #[test]
+    fn hint_reports_disc_values() {
+        let mut nboard = nboard();
+        let reply = nboard.handle("hint 2");
+        assert_eq!(reply.len(), 2);
+        for line in reply {
+            let terms = line.split(' ').collect::<Vec<&str>>();
+            assert_eq!(terms.len(), 5, "{}", line);
+            assert_eq!(terms[0], "search");
+            assert!(valid_moves(&init_board(), true).contains(&parse_square(terms[1])));
+            assert!(terms[2].parse::<f64>().unwrap().abs() < 64.0);
+            assert_eq!(terms[3], "0");
+            assert_eq!(terms[4], "5");
+        }
+    }
+}
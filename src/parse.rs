@@ -1,85 +1,312 @@
+use crate::ai::book_values;
+use crate::book::{Book, BookFilter};
+use crate::color::Color;
 use crate::command::Move;
+use crate::ggf::load_ggf;
 use crate::play::{do_move, init_board};
-use crate::play::{valid_moves, Board};
+use crate::play::{parse_square, valid_moves, Board};
+use crate::wthor::load_wthor;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineError {
+    Empty,
+    MissingResult,
+    InvalidResult(String),
+    InvalidMove(usize, String),
+    IllegalMove(usize, String),
+    WrongColor(usize, String),
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineError::Empty => write!(f, "empty line"),
+            LineError::MissingResult => write!(f, "missing result"),
+            LineError::InvalidResult(r) => write!(f, "invalid result {}", r),
+            LineError::InvalidMove(n, mv) => write!(f, "move {}: cannot parse {}", n, mv),
+            LineError::IllegalMove(n, mv) => write!(f, "move {}: {} is illegal", n, mv),
+            LineError::WrongColor(n, mv) => {
+                write!(f, "move {}: {} played out of turn", n, mv)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub rejected: usize,
+}
+
+// 手を打った側と (列, 行)
+type ParsedMove = (Color, (i8, i8));
+
+// "+d3-c3+c4..." の形式. 符号が手を打った側 (+: 黒, -: 白) で,
+// 同じ符号が続いたときは間で相手がパスしたとみなす.
+fn parse_moves(str: &str) -> Result<Vec<ParsedMove>, LineError> {
+    let bytes = str.as_bytes();
+    let mut moves = vec![];
+    for (n, chunk) in bytes.chunks(3).enumerate() {
+        let text = String::from_utf8_lossy(chunk).to_string();
+        let color = match chunk[0] {
+            b'+' => true,
+            b'-' => false,
+            _ => return Err(LineError::InvalidMove(n + 1, text)),
+        };
+        if chunk.len() != 3 {
+            return Err(LineError::InvalidMove(n + 1, text));
+        }
+        match parse_square(&chunk[1..]) {
+            Some(pos) => moves.push((color, pos)),
+            None => return Err(LineError::InvalidMove(n + 1, text)),
+        }
+    }
+    Ok(moves)
+}
 
-static mut WL: Lazy<HashMap<Board, (i32, i32)>> = Lazy::new(|| HashMap::new());
-static FST_STEP: usize = 20;
+// 最初の depth 手について, 勝った側と負けた側が打った直後の盤面を返す
+pub fn parse_line(line: &str, depth: usize) -> Result<(Vec<Board>, Vec<Board>), LineError> {
+    let terms = line.split_whitespace().collect::<Vec<&str>>();
+    let (cmd_term, result) = match terms[..] {
+        [] => return Err(LineError::Empty),
+        [_] => return Err(LineError::MissingResult),
+        [cmd_term, result, ..] => (cmd_term, result),
+    };
+    let black_wins = match result.as_bytes()[0] {
+        b'+' => true,
+        b'-' => false,
+        _ => return Err(LineError::InvalidResult(result.to_string())),
+    };
 
-fn parse_line(line: &str) -> (Vec<Board>, Vec<Board>) {
     let mut p = vec![];
     let mut o = vec![];
-    let terms = line.split_whitespace().collect::<Vec<&str>>();
-    let cmd_term = terms.first().unwrap();
-    let cmds = cmd_term.split(['+', '-']).collect::<Vec<&str>>();
     let mut board = init_board();
-    for i in 1..(FST_STEP + 1) {
-        let x = (cmds[i].as_bytes())[0] as i8 - 'a' as i8;
-        let y = (cmds[i].as_bytes())[1] as i8 - '1' as i8;
-        assert!(valid_moves(&board, i % 2 == 1).contains(&(x, y)));
-        do_move(&mut board, &Move::Mv(x, y), i % 2 == 1);
-        if i % 2 == 1 {
-            p.push(board.clone());
-        } else {
-            o.push(board.clone());
+    let mut color = true;
+    let moves = parse_moves(cmd_term.trim_end_matches(':'))?;
+    for (n, (mover, (x, y))) in moves.into_iter().enumerate() {
+        let text = || format!("{}{}", (b'a' + x as u8) as char, y + 1);
+        if mover != color {
+            if !valid_moves(&board, color).is_empty() {
+                return Err(LineError::WrongColor(n + 1, text()));
+            }
+            color = mover;
+        }
+        if !valid_moves(&board, color).contains(&(x, y)) {
+            return Err(LineError::IllegalMove(n + 1, text()));
+        }
+        do_move(&mut board, &Move::Mv(x, y), color);
+        if n < depth {
+            if color {
+                p.push(board);
+            } else {
+                o.push(board);
+            }
         }
+        color = !color;
     }
-    if terms[1].as_bytes()[0] as u8 == '+' as u8 {
-        (p, o)
+    if black_wins {
+        Ok((p, o))
     } else {
-        (o, p)
+        Ok((o, p))
     }
 }
 
-pub fn create_database() {
-    let file = File::open("src/logbook.gam").expect("file not found");
+pub fn import_logbook(book: &mut Book, path: &str, depth: usize) -> std::io::Result<ImportSummary> {
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
 
-    for line in reader.lines() {
-        let (w, l) = parse_line(&line.unwrap());
-        for b in w {
-            unsafe {
-                if WL.contains_key(&b) {
-                    (*WL.get_mut(&b).unwrap()).0 += 1;
-                } else {
-                    WL.insert(b, (1, 0));
+    let mut summary = ImportSummary::default();
+    for (n, line) in reader.lines().enumerate() {
+        match parse_line(&line?, depth) {
+            Ok((w, l)) => {
+                for b in w {
+                    book.add(b, 1, 0);
+                }
+                for b in l {
+                    book.add(b, 0, 1);
+                }
+                summary.imported += 1;
+            }
+            Err(e) => {
+                println!("{}:{}: skipped ({})", path, n + 1, e);
+                summary.rejected += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+type Importer = fn(&mut Book, &str, usize) -> std::io::Result<ImportSummary>;
+
+// WTHOR の各対局を再生し, 勝った側と負けた側の局面を book に加える. 引き分けは数えない
+pub fn import_wthor(book: &mut Book, path: &str, depth: usize) -> std::io::Result<ImportSummary> {
+    let (_, games) = load_wthor(path)?;
+    let mut summary = ImportSummary::default();
+    for (n, game) in games.iter().enumerate() {
+        match game.replay() {
+            Ok((moves, _)) => {
+                if let Some(winner) = game.winner() {
+                    book.learn(&moves, winner, depth);
                 }
+                summary.imported += 1;
+            }
+            Err(k) => {
+                println!("{}: game {}: skipped (move {} is illegal)", path, n + 1, k);
+                summary.rejected += 1;
             }
         }
-        for b in l {
-            unsafe {
-                if WL.contains_key(&b) {
-                    (*WL.get_mut(&b).unwrap()).1 += 1;
-                } else {
-                    WL.insert(b, (0, 1));
+    }
+    Ok(summary)
+}
+
+// GGF の対局のうち, 初期局面から始まり手順が正しいものを book に加える
+pub fn import_ggf(book: &mut Book, path: &str, depth: usize) -> std::io::Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+    for (n, game) in load_ggf(path)?.into_iter().enumerate() {
+        let checked = game.and_then(|game| {
+            game.replay()?;
+            Ok(game)
+        });
+        match checked {
+            Ok(game) if game.is_standard_start() => {
+                if let Some(winner) = game.winner() {
+                    let moves = game.moves.into_iter().map(|m| m.1).collect::<Vec<Move>>();
+                    book.learn(&moves, winner, depth);
                 }
+                summary.imported += 1;
             }
+            Ok(_) => {
+                println!(
+                    "{}: game {}: skipped (not from the initial position)",
+                    path,
+                    n + 1
+                );
+                summary.rejected += 1;
+            }
+            Err(e) => {
+                println!("{}: game {}: skipped ({})", path, n + 1, e);
+                summary.rejected += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+pub fn create_database(
+    path: &str,
+    filter: &BookFilter,
+    eval_depth: i8,
+    wthor: &[String],
+    ggf: &[String],
+) {
+    let mut book = Book::new();
+    let mut sources = vec![("src/logbook.gam".to_string(), import_logbook as Importer)];
+    sources.extend(wthor.iter().map(|p| (p.clone(), import_wthor as Importer)));
+    sources.extend(ggf.iter().map(|p| (p.clone(), import_ggf as Importer)));
+    for (source, import) in sources {
+        let summary = import(&mut book, &source, filter.depth);
+        match summary {
+            Ok(summary) => println!(
+                "{}: imported {} games, rejected {}.",
+                source, summary.imported, summary.rejected
+            ),
+            Err(e) => println!("{}: cannot read ({}).", source, e),
         }
     }
-    let file = File::create("src/database.rs").expect("failed creating file");
-    // let file = File::create("database").expect("failed creating file");
-    writeln!(
-        &file,
-        "use crate::play::Board;
-         use once_cell::sync::Lazy;
-         use std::collections::HashMap;
-        "
-    )
-    .unwrap();
-    writeln!(
-        &file,
-        "pub static DATABASE: Lazy<HashMap<Board, (i32, i32)>> =Lazy::new(|| ["
-    )
-    .unwrap();
-    unsafe {
-        for (k, v) in WL.iter() {
-            writeln!(&file, "(({}, {}), ({}, {})),", k.0, k.1, v.0, v.1).unwrap();
+    book.retain(filter);
+    let leaves = book_values(&mut book, eval_depth);
+    println!("Propagated values from {} leaves.", leaves);
+    book.save(path).expect("failed writing book");
+    println!("Saved {} positions to {}.", book.len(), path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wthor::load_wthor;
+
+    // 手順を logbook の形式に直す. パスは書かない
+    fn logbook_line(moves: &[Move], result: &str) -> String {
+        let mut line = String::new();
+        let mut color = true;
+        for mv in moves {
+            if let Move::Mv(i, j) = mv {
+                let sign = if color { '+' } else { '-' };
+                line.push_str(&format!("{}{}{}", sign, (b'a' + *i as u8) as char, j + 1));
+            }
+            color = !color;
         }
+        format!("{}: {} 1", line, result)
+    }
+
+    #[test]
+    fn same_sign_run_is_a_pass() {
+        let (_, games) = load_wthor("tests/fixtures/tiny.wtb").unwrap();
+        let (moves, board) = games[1].replay().unwrap();
+        assert!(moves.contains(&Move::Pass));
+        let played = moves.iter().filter(|mv| **mv != Move::Pass).count();
+        let (w, l) = parse_line(&logbook_line(&moves, "+10"), 60).unwrap();
+        assert_eq!(w.len() + l.len(), played);
+        assert!(w.last() == Some(&board) || l.last() == Some(&board));
+    }
+
+    #[test]
+    fn keeps_positions_up_to_depth_for_each_side() {
+        let mut board = init_board();
+        do_move(&mut board, &Move::Mv(5, 4), true);
+        let after_f5 = board;
+        do_move(&mut board, &Move::Mv(3, 5), false);
+        let after_d6 = board;
+        let line = "+f5-d6+c3-d3: +10 1";
+        assert_eq!(parse_line(line, 2), Ok((vec![after_f5], vec![after_d6])));
+        let line = "+f5-d6+c3-d3: -10 1";
+        assert_eq!(parse_line(line, 2), Ok((vec![after_d6], vec![after_f5])));
+        let (w, l) = parse_line(line, 60).unwrap();
+        assert_eq!((w.len(), l.len()), (2, 2));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert_eq!(parse_line("", 60), Err(LineError::Empty));
+        assert_eq!(parse_line("+f5-d6:", 60), Err(LineError::MissingResult));
+        assert_eq!(
+            parse_line("+f5-d6: x10", 60),
+            Err(LineError::InvalidResult("x10".to_string()))
+        );
+        assert_eq!(
+            parse_line("+f5+d6: +10", 60),
+            Err(LineError::WrongColor(2, "d6".to_string()))
+        );
+        assert_eq!(
+            parse_line("+f5-c4: +10", 60),
+            Err(LineError::IllegalMove(2, "c4".to_string()))
+        );
+        assert_eq!(
+            parse_line("+f5-z9: +10", 60),
+            Err(LineError::InvalidMove(2, "-z9".to_string()))
+        );
+        assert_eq!(
+            parse_line("+f5-d: +10", 60),
+            Err(LineError::InvalidMove(2, "-d".to_string()))
+        );
+    }
+
+    #[test]
+    fn import_counts_imported_and_rejected_lines() {
+        let path = std::env::temp_dir().join(format!("logbook-{}.gam", std::process::id()));
+        let text = "+f5-d6+c3-d3: +10 1\n+f5+d6: +10 1\n\n+f5-f6+e6-f4: -4 1\n";
+        std::fs::write(&path, text).unwrap();
+        let mut book = Book::new();
+        let summary = import_logbook(&mut book, path.to_str().unwrap(), 60).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((summary.imported, summary.rejected), (2, 2));
+
+        let mut board = init_board();
+        do_move(&mut board, &Move::Mv(5, 4), true);
+        assert_eq!(book.get(&board), Some(&(1, 1)));
     }
-    writeln!(&file, "].iter().cloned().collect());").unwrap();
 }
@@ -139,6 +139,41 @@ pub fn do_move(board: &mut Board, com: &Move, color: Color) {
     }
 }
 
+// "f5" のような2文字のマス. 列は大文字でもよい
+pub fn parse_square(str: &[u8]) -> Option<(i8, i8)> {
+    let x = str[0].to_ascii_lowercase().wrapping_sub(b'a');
+    let y = str[1].wrapping_sub(b'1');
+    if x < 8 && y < 8 {
+        Some((x as i8, y as i8))
+    } else {
+        None
+    }
+}
+
+// "f5d6c3" のような手順を読む. パスは書かず, 打てる手がなければ手番を飛ばす
+pub fn parse_move_list(line: &str) -> Option<Vec<Move>> {
+    let line = line.trim();
+    if !line.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut board = init_board();
+    let mut color = true;
+    let mut moves = vec![];
+    for sq in line.as_bytes().chunks(2) {
+        let (i, j) = parse_square(sq)?;
+        if valid_moves(&board, color).is_empty() {
+            color = !color;
+        }
+        if !valid_moves(&board, color).contains(&(i, j)) {
+            return None;
+        }
+        do_move(&mut board, &Move::Mv(i, j), color);
+        moves.push(Move::Mv(i, j));
+        color = !color;
+    }
+    Some(moves)
+}
+
 pub fn count(board: u64) -> i8 {
     let mut bits = board;
     bits = (bits & 0x5555555555555555) + ((bits >> 1) & 0x5555555555555555);
@@ -188,6 +223,14 @@ pub fn get_result(board: &Board, color: Color) -> i8 {
     }
 }
 
+pub fn get_disc_diff(board: &Board, color: Color) -> i8 {
+    if color {
+        count(board.0) - count(board.1)
+    } else {
+        count(board.1) - count(board.0)
+    }
+}
+
 fn calc_weight(board: u64) -> i32 {
     let mut board = board;
     /*static WEIGHTS: [i32; 64] = [
@@ -265,7 +308,32 @@ pub fn openness(flippable_bits: u64, pboard: u64, oboard: u64) -> i32 {
     count(neighbors) as i32
 }
 
-pub fn evaluate(board: &Board, board_p: &Board, color: Color, depth: i8) -> i32 {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvalWeights {
+    pub openness: i32,
+    pub weight: i32,
+    pub candidates: i32,
+    pub stable: i32,
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights {
+            openness: 10,
+            weight: 1,
+            candidates: 10,
+            stable: 50,
+        }
+    }
+}
+
+pub fn evaluate(
+    board: &Board,
+    board_p: &Board,
+    color: Color,
+    depth: i8,
+    weights: &EvalWeights,
+) -> i32 {
     let pboard: u64;
     let oboard: u64;
     let oboard_p: u64;
@@ -290,10 +358,10 @@ pub fn evaluate(board: &Board, board_p: &Board, color: Color, depth: i8) -> i32
         let ocandidates = count(flippable_bits(oboard, pboard)) as i32;
         let pstables = count_stable(pboard);
         let ostables = count_stable(oboard);
-        openess * 10
-            + (pweight - oweight)
-            + 10 * (pcandidates - ocandidates)
-            + 50 * (pstables - ostables)
+        weights.openness * openess
+            + weights.weight * (pweight - oweight)
+            + weights.candidates * (pcandidates - ocandidates)
+            + weights.stable * (pstables - ostables)
     }
 }
 
@@ -336,3 +404,68 @@ pub fn expand(board: &Board) -> Vec<Board> {
     }
     res
 }
+
+pub fn canonical(board: &Board) -> Board {
+    expand(board).into_iter().min().unwrap()
+}
+
+// expand の並び順に対応する変換の名前
+pub const SYMMETRY_NAMES: [&str; 8] = [
+    "identity",
+    "flip vertical",
+    "rotate 180",
+    "flip vertical, rotate 180",
+    "flip diagonal",
+    "flip vertical, flip diagonal",
+    "rotate 180, flip diagonal",
+    "flip vertical, rotate 180, flip diagonal",
+];
+
+// canonical と, それを与えた expand の添字 (SYMMETRY_NAMES の添字) を返す
+pub fn canonical_with_symmetry(board: &Board) -> (Board, usize) {
+    expand(board)
+        .into_iter()
+        .enumerate()
+        .min_by_key(|(_, b)| *b)
+        .map(|(i, b)| (b, i))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SYMMETRY_NAMES の名前どおりに変換を順にかける
+    fn apply_named(board: &Board, name: &str) -> Board {
+        let mut board = *board;
+        for step in name.split(", ") {
+            let f = match step {
+                "identity" => |data| data,
+                "flip vertical" => flip_vertical_data,
+                "rotate 180" => rotate180_data,
+                "flip diagonal" => flip_diagonal_data,
+                _ => panic!("unknown symmetry {}", step),
+            };
+            board = (f(board.0), f(board.1));
+        }
+        board
+    }
+
+    #[test]
+    fn symmetry_names_match_expand() {
+        let mut board = init_board();
+        for (i, j) in [(5, 4), (3, 5), (2, 2)] {
+            let color = count(board.0 | board.1) % 2 == 0;
+            do_move(&mut board, &Move::Mv(i, j), color);
+        }
+        let boards = expand(&board);
+        for (k, name) in SYMMETRY_NAMES.iter().enumerate() {
+            assert_eq!(boards[k], apply_named(&board, name), "{}", name);
+        }
+        for b in &boards {
+            let (c, k) = canonical_with_symmetry(b);
+            assert_eq!(c, canonical(&board));
+            assert_eq!(apply_named(b, SYMMETRY_NAMES[k]), c);
+        }
+    }
+}
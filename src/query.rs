@@ -0,0 +1,68 @@
+use crate::ai::{format_score, string_of_pv, Analysis, JosekiCandidate};
+use crate::color::Color;
+use crate::command::Move;
+use crate::ggf::board_from_squares;
+use crate::play::{do_move, init_board, parse_move_list, valid_moves, Board, SYMMETRY_NAMES};
+
+// "f5d6c3" のような手順か, A1, B1, ..., H8 の順に並べた64文字の盤面 (-: 空, X: 黒, O: 白)
+// と手番 (X または O) を読む
+pub fn parse_position(str: &str) -> Option<(Board, Color)> {
+    if let Ok(position) = board_from_squares(str) {
+        return Some(position);
+    }
+    let str = str.split_whitespace().collect::<String>();
+    let mut board = init_board();
+    let mut color = true;
+    for mv in parse_move_list(&str)? {
+        if valid_moves(&board, color).is_empty() {
+            color = !color;
+        }
+        do_move(&mut board, &mv, color);
+        color = !color;
+    }
+    if valid_moves(&board, color).is_empty() {
+        color = !color;
+    }
+    Some((board, color))
+}
+
+// book の続きの手の表. probs は joseki_probabilities の値
+pub fn book_table(candidates: &[JosekiCandidate], probs: &[f64]) -> Vec<String> {
+    let mut lines = vec!["Move   Wins Losses   Rate  Value  Prob  Symmetry".to_string()];
+    for (c, p) in candidates.iter().zip(probs) {
+        let games = c.wins + c.losses;
+        let rate = if games > 0 {
+            c.wins as f64 / games as f64 * 100.0
+        } else {
+            0.0
+        };
+        lines.push(format!(
+            "{}{} {:>7} {:>6} {:>5.1}% {:>6} {:>5.1}%  {}",
+            Move::Mv(c.action.0, c.action.1),
+            if c.accepted { " " } else { "*" },
+            c.wins,
+            c.losses,
+            rate,
+            c.value.map_or("-".to_string(), |v| v.to_string()),
+            p * 100.0,
+            SYMMETRY_NAMES[c.symmetry],
+        ));
+    }
+    lines.push("(*: rejected by the filter)".to_string());
+    lines
+}
+
+// Engine::analyze の結果の表
+pub fn analysis_table(res: &[Analysis]) -> Vec<String> {
+    let mut lines = vec!["Move  Score  Depth  PV".to_string()];
+    for a in res {
+        lines.push(format!(
+            "{}  {:>6} {:>6}  {}",
+            Move::Mv(a.action.0, a.action.1),
+            format_score(a.score),
+            a.depth,
+            string_of_pv(&a.pv)
+        ));
+    }
+    lines
+}
@@ -0,0 +1,202 @@
+use crate::color::Color;
+use crate::play::Board;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const NO_MOVE: u64 = 0xff;
+
+const fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+const fn init_keys() -> [[u64; 64]; 2] {
+    let mut keys = [[0; 64]; 2];
+    let mut i = 0;
+    while i < 64 {
+        keys[0][i] = splitmix64(i as u64);
+        keys[1][i] = splitmix64(64 + i as u64);
+        i += 1;
+    }
+    keys
+}
+
+static KEYS: [[u64; 64]; 2] = init_keys();
+const SIDE_KEY: u64 = splitmix64(128);
+
+fn hash_bits(bits: u64, keys: &[u64; 64]) -> u64 {
+    let mut bits = bits;
+    let mut res = 0;
+    while bits != 0 {
+        res ^= keys[bits.trailing_zeros() as usize];
+        bits &= bits - 1;
+    }
+    res
+}
+
+pub fn hash(board: &Board, color: Color) -> u64 {
+    let side = if color { SIDE_KEY } else { 0 };
+    hash_bits(board.0, &KEYS[0]) ^ hash_bits(board.1, &KEYS[1]) ^ side
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Exact = 0,
+    Lower = 1,
+    Upper = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub score: i32,
+    pub depth: i8,
+    pub bound: Bound,
+    pub best: Option<(i8, i8)>,
+}
+
+impl Entry {
+    fn pack(&self) -> u64 {
+        let best = match self.best {
+            Some((i, j)) => (i * 8 + j) as u64,
+            None => NO_MOVE,
+        };
+        (self.score as u32 as u64)
+            | ((self.depth as u8 as u64) << 32)
+            | ((self.bound as u64) << 40)
+            | (best << 48)
+    }
+
+    fn unpack(data: u64) -> Entry {
+        let bound = match (data >> 40) & 0xff {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let best = match (data >> 48) & 0xff {
+            NO_MOVE => None,
+            pos => Some(((pos / 8) as i8, (pos % 8) as i8)),
+        };
+        Entry {
+            score: data as u32 as i32,
+            depth: (data >> 32) as u8 as i8,
+            bound,
+            best,
+        }
+    }
+}
+
+// 各スロットは (key ^ data, data) の組で持ち, 複数スレッドから同時に書かれて
+// 壊れたエントリは key が一致しないので読み捨てられる.
+pub struct TransTable {
+    entries: Vec<(AtomicU64, AtomicU64)>,
+    mask: u64,
+}
+
+impl TransTable {
+    pub fn new(bits: u32) -> TransTable {
+        let size = 1usize << bits;
+        let mut entries = Vec::with_capacity(size);
+        entries.resize_with(size, || (AtomicU64::new(0), AtomicU64::new(0)));
+        TransTable {
+            entries,
+            mask: (size - 1) as u64,
+        }
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let (check, data) = &self.entries[(key & self.mask) as usize];
+        let data = data.load(Ordering::Relaxed);
+        let check = check.load(Ordering::Relaxed);
+        if data == 0 || check ^ data != key {
+            return None;
+        }
+        Some(Entry::unpack(data))
+    }
+
+    pub fn store(&self, key: u64, entry: Entry) {
+        let (check, data) = &self.entries[(key & self.mask) as usize];
+        let packed = entry.pack();
+        check.store(key ^ packed, Ordering::Relaxed);
+        data.store(packed, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for (check, data) in &self.entries {
+            check.store(0, Ordering::Relaxed);
+            data.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::Engine;
+    use crate::command::Move;
+    use crate::play::{do_move, init_board, valid_moves};
+
+    #[test]
+    fn entries_round_trip() {
+        for score in [0, -1, 12345, -987654, i32::MAX, -i32::MAX] {
+            for best in [None, Some((0, 0)), Some((7, 7)), Some((3, 5))] {
+                for bound in [Bound::Exact, Bound::Lower, Bound::Upper] {
+                    let entry = Entry {
+                        score,
+                        depth: 9,
+                        bound,
+                        best,
+                    };
+                    assert_eq!(Entry::unpack(entry.pack()), entry);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn probe_checks_the_key() {
+        let tt = TransTable::new(4);
+        let entry = Entry {
+            score: -5,
+            depth: 3,
+            bound: Bound::Lower,
+            best: None,
+        };
+        let key = hash(&init_board(), true);
+        assert_eq!(tt.probe(key), None);
+        tt.store(key, entry);
+        assert_eq!(tt.probe(key), Some(entry));
+        // 同じスロットでも key が違えば外れる
+        assert_eq!(tt.probe(key ^ (1 << 8)), None);
+        tt.clear();
+        assert_eq!(tt.probe(key), None);
+    }
+
+    #[test]
+    fn hash_depends_on_side_to_move() {
+        let board = init_board();
+        assert_ne!(hash(&board, true), hash(&board, false));
+        assert_ne!(hash(&board, true), hash(&(board.1, board.0), true));
+    }
+
+    // 置換表が空でも, 浅い探索で埋まっていても同じ値になる
+    #[test]
+    fn cold_and_warm_tables_agree() {
+        let mut board = init_board();
+        let mut color = true;
+        for _ in 0..12 {
+            let moves = valid_moves(&board, color);
+            let (i, j) = moves[moves.len() / 2];
+            do_move(&mut board, &Move::Mv(i, j), color);
+            color = !color;
+        }
+        let mover = !color;
+        for depth in [3, 4, 5] {
+            let warm = Engine::new();
+            warm.search_value(&board, mover, depth - 1);
+            let cold = Engine::new().search_value(&board, mover, depth);
+            assert_eq!(warm.search_value(&board, mover, depth), cold);
+            assert_eq!(warm.search_value(&board, mover, depth), cold);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::color::Color;
+use crate::command::Move;
+use crate::play::{do_move, init_board, valid_moves, Board};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+
+// WTHOR (.wtb) 形式. 16 バイトのヘッダの後に 68 バイトの対局が並ぶ.
+// 各手は 10 * 行 + 列 (1 始まり) の1バイトで, パスは書かれない. 0 は以降の手がないことを表す
+const HEADER_SIZE: usize = 16;
+const GAME_SIZE: usize = 68;
+
+#[derive(Debug, Clone)]
+pub struct WthorHeader {
+    pub games: u32,
+    pub year: u16,
+    pub board_size: u8,
+    pub depth: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct WthorGame {
+    pub tournament: u16,
+    pub black: u16,
+    pub white: u16,
+    // 終局時の黒石の数と, depth 手前からの完全読みによる黒石の数
+    pub black_discs: u8,
+    pub theoretical: u8,
+    pub squares: Vec<(i8, i8)>,
+}
+
+impl WthorGame {
+    // 実際の石数による勝者. 引き分けは None
+    pub fn winner(&self) -> Option<Color> {
+        match self.black_discs.cmp(&32) {
+            std::cmp::Ordering::Greater => Some(true),
+            std::cmp::Ordering::Less => Some(false),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    // 手順を再生し, 必要なところに Move::Pass を補った手の列と終局図を返す.
+    // 不正な手があればその手数 (1 始まり) を返す
+    pub fn replay(&self) -> Result<(Vec<Move>, Board), usize> {
+        let mut board = init_board();
+        let mut color = true;
+        let mut moves = vec![];
+        for (n, &(i, j)) in self.squares.iter().enumerate() {
+            if valid_moves(&board, color).is_empty() {
+                moves.push(Move::Pass);
+                color = !color;
+            }
+            if !valid_moves(&board, color).contains(&(i, j)) {
+                return Err(n + 1);
+            }
+            do_move(&mut board, &Move::Mv(i, j), color);
+            moves.push(Move::Mv(i, j));
+            color = !color;
+        }
+        Ok((moves, board))
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_header(bytes: &[u8; HEADER_SIZE]) -> WthorHeader {
+    WthorHeader {
+        games: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        year: u16::from_le_bytes(bytes[10..12].try_into().unwrap()),
+        board_size: bytes[12],
+        depth: bytes[14],
+    }
+}
+
+fn read_game(bytes: &[u8; GAME_SIZE], n: usize) -> io::Result<WthorGame> {
+    let mut squares = vec![];
+    for &b in bytes[8..].iter().take_while(|&&b| b != 0) {
+        let (row, col) = (b / 10, b % 10);
+        if !(1..=8).contains(&row) || !(1..=8).contains(&col) {
+            return Err(invalid_data(format!(
+                "game {}: invalid square {}",
+                n + 1,
+                b
+            )));
+        }
+        squares.push(((col - 1) as i8, (row - 1) as i8));
+    }
+    Ok(WthorGame {
+        tournament: u16::from_le_bytes(bytes[0..2].try_into().unwrap()),
+        black: u16::from_le_bytes(bytes[2..4].try_into().unwrap()),
+        white: u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        black_discs: bytes[6],
+        theoretical: bytes[7],
+        squares,
+    })
+}
+
+pub fn read_wthor<R: Read>(mut reader: R) -> io::Result<(WthorHeader, Vec<WthorGame>)> {
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let header = read_header(&header);
+    if header.board_size != 0 && header.board_size != 8 {
+        return Err(invalid_data(format!(
+            "unsupported board size {}",
+            header.board_size
+        )));
+    }
+    let mut games = vec![];
+    let mut record = [0; GAME_SIZE];
+    for n in 0..header.games as usize {
+        reader.read_exact(&mut record)?;
+        games.push(read_game(&record, n)?);
+    }
+    Ok((header, games))
+}
+
+pub fn load_wthor(path: &str) -> io::Result<(WthorHeader, Vec<WthorGame>)> {
+    read_wthor(BufReader::new(File::open(path)?))
+}
@@ -0,0 +1,42 @@
+use reversi_rust::arena::ArenaStats;
+
+// 対局数 0 (-n 0) でも NaN にならない
+#[test]
+fn stats_without_games() {
+    let stats = ArenaStats::default();
+    assert_eq!(stats.score(), 0.5);
+    assert_eq!(stats.average_margin(), 0.0);
+    assert_eq!(stats.elo(), (0.0, f64::INFINITY));
+}
+
+#[test]
+fn stats_after_games() {
+    let mut stats = ArenaStats::default();
+    stats.add(1, 10);
+    stats.add(0, 0);
+    stats.add(-1, -4);
+    assert_eq!(stats.games(), 3);
+    assert_eq!(stats.score(), 0.5);
+    assert_eq!(stats.average_margin(), 2.0);
+    assert_eq!(stats.elo().0, 0.0);
+}
+
+// 全勝や全敗では誤差を 0 としない
+#[test]
+fn one_sided_results_have_unbounded_error() {
+    let mut stats = ArenaStats::default();
+    stats.add(1, 20);
+    stats.add(1, 8);
+    let (elo, error) = stats.elo();
+    assert!(elo > 1000.0);
+    assert_eq!(error, f64::INFINITY);
+
+    let mut stats = ArenaStats::default();
+    stats.add(-1, -2);
+    assert_eq!(stats.elo().1, f64::INFINITY);
+
+    stats.add(1, 2);
+    let (elo, error) = stats.elo();
+    assert_eq!(elo, 0.0);
+    assert!(error.is_finite() && error > 0.0);
+}
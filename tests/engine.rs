@@ -0,0 +1,152 @@
+use reversi_rust::play::count;
+use reversi_rust::query::parse_position;
+use reversi_rust::{
+    do_move, get_disc_diff, init_board, valid_moves, Board, Color, Engine, EngineConfig, Move,
+};
+
+// 各手番で最初の合法手を打ち, 空きマスが empties になった局面と手番を返す
+fn position_with_empties(empties: i8) -> (Board, Color) {
+    let mut board = init_board();
+    let mut color = true;
+    while 64 - count(board.0 | board.1) > empties {
+        let moves = valid_moves(&board, color);
+        if let Some(&(i, j)) = moves.first() {
+            do_move(&mut board, &Move::Mv(i, j), color);
+        }
+        color = !color;
+    }
+    if valid_moves(&board, color).is_empty() {
+        color = !color;
+    }
+    (board, color)
+}
+
+// 途中の局面から始めても, 終盤は book や中盤探索ではなく完全読みで打つ
+#[test]
+fn late_position_uses_endgame_solver() {
+    let (board, color) = position_with_empties(14);
+    let mut engine = Engine::new();
+    engine.init_ai_from(color, &board);
+    engine.set_time_remain(600000);
+    let mv = engine.play(&board, color, false);
+    assert!(matches!(mv, Move::Mv(_, _)));
+    assert!(engine.stats().end > 0);
+}
+
+// 終局まで全て読んだ石差
+fn minimax(board: &Board, color: Color, is_passed: bool) -> i8 {
+    let moves = valid_moves(board, color);
+    if moves.is_empty() {
+        if is_passed {
+            return get_disc_diff(board, color);
+        }
+        return -minimax(board, !color, true);
+    }
+    let mut best = -64;
+    for (i, j) in moves {
+        let mut n_board = *board;
+        do_move(&mut n_board, &Move::Mv(i, j), color);
+        best = best.max(-minimax(&n_board, !color, false));
+    }
+    best
+}
+
+fn margin_after(board: &Board, color: Color, mv: Move) -> i8 {
+    let mut n_board = *board;
+    do_move(&mut n_board, &mv, color);
+    -minimax(&n_board, !color, false)
+}
+
+fn play_with_exact_empties(board: &Board, color: Color, exact_empties: u8) -> Move {
+    let mut engine = Engine::with_config(EngineConfig {
+        exact_empties,
+        ..EngineConfig::default()
+    });
+    engine.init_ai_from(color, board);
+    engine.set_time_remain(600000);
+    let mv = engine.play(board, color, false);
+    assert!(engine.stats().end > 0);
+    mv
+}
+
+// 勝ちの局面では最大の石差, 負けの局面では最小の石差の手を選ぶ.
+// 勝敗だけを読む設定では石差の劣る手を選ぶ局面を使う
+#[test]
+fn exact_empties_selects_solve_mode() {
+    for (position, winning) in [
+        (
+            "-XXXXXO-XXXXXOOOXXOOXXOOXXOXOXOO-OXXXXXXOOOXXXXX-OOXOOOXO-XOOO-XX",
+            true,
+        ),
+        (
+            "OXXX-OOO-O-XXXOOOXOXXXOOOXOOOOX-XXXOOOXXXXXOOOXOXXXXXXX-X-XXXXXOX",
+            false,
+        ),
+    ] {
+        let (board, color) = parse_position(position).unwrap();
+        let best = valid_moves(&board, color)
+            .into_iter()
+            .map(|(i, j)| margin_after(&board, color, Move::Mv(i, j)))
+            .max()
+            .unwrap();
+        assert_eq!(best > 0, winning);
+
+        let exact = play_with_exact_empties(&board, color, 64);
+        assert_eq!(margin_after(&board, color, exact), best);
+        let wld = margin_after(&board, color, play_with_exact_empties(&board, color, 0));
+        assert_eq!(wld > 0, winning);
+        assert!(wld < best);
+    }
+}
+
+// depth が 5 未満だと中盤探索が止まらないので, 設定は 5 に丸める
+#[test]
+fn shallow_depth_is_clamped() {
+    let engine = Engine::with_config(EngineConfig {
+        depth: 3,
+        ..EngineConfig::default()
+    });
+    assert_eq!(engine.depth(), 5);
+}
+
+// pv を board から打ち進め, すべて合法手か確かめる
+fn assert_legal_pv(board: &Board, color: Color, pv: &[Move]) {
+    let mut board = *board;
+    let mut color = color;
+    for mv in pv {
+        match mv {
+            Move::Mv(i, j) => {
+                assert!(valid_moves(&board, color).contains(&(*i, *j)), "{:?}", pv);
+                do_move(&mut board, mv, color);
+            }
+            Move::Pass => assert!(valid_moves(&board, color).is_empty(), "{:?}", pv),
+            Move::GiveUp => panic!("unexpected move in {:?}", pv),
+        }
+        color = !color;
+    }
+}
+
+// 準備なしの engine でも全ての合法手を良い順に返す
+#[test]
+fn analyze_returns_every_move_best_first() {
+    for (board, color, depth) in [(init_board(), true, 5), {
+        let (board, color) = position_with_empties(20);
+        (board, color, 6)
+    }] {
+        let mut engine = Engine::new();
+        let res = engine.analyze(&board, color, depth, 600000);
+        let mut actions = res.iter().map(|a| a.action).collect::<Vec<_>>();
+        let mut moves = valid_moves(&board, color);
+        actions.sort();
+        moves.sort();
+        assert_eq!(actions, moves);
+        for w in res.windows(2) {
+            assert!(w[0].score >= w[1].score);
+        }
+        for a in &res {
+            assert_eq!(a.depth, depth);
+            assert_eq!(a.pv.first(), Some(&Move::Mv(a.action.0, a.action.1)));
+            assert_legal_pv(&board, color, &a.pv);
+        }
+    }
+}
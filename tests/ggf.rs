@@ -0,0 +1,72 @@
+use reversi_rust::ggf::{parse_ggf, GgfError, GgfGame};
+use reversi_rust::wthor::load_wthor;
+use reversi_rust::{init_board, Move};
+
+// WTHOR の fixture の2局目は途中にパスがある
+fn game_with_pass() -> (GgfGame, reversi_rust::Board) {
+    let (_, games) = load_wthor("tests/fixtures/tiny.wtb").unwrap();
+    let (moves, board) = games[1].replay().unwrap();
+    assert!(moves.contains(&Move::Pass));
+    let mut game = GgfGame::new(init_board(), true);
+    game.tags.push(("GM".to_string(), "Othello".to_string()));
+    game.tags.push(("PB".to_string(), "a]b\\c".to_string()));
+    game.tags.push(("RE".to_string(), "+10".to_string()));
+    let mut color = true;
+    for mv in moves {
+        game.moves.push((color, mv, "/1.00/2".to_string()));
+        color = !color;
+    }
+    (game, board)
+}
+
+#[test]
+fn writes_and_reads_back_games() {
+    let (game, board) = game_with_pass();
+    let text = game.to_ggf();
+    assert!(text.contains("PB[a\\]b\\\\c]"));
+    assert!(text.contains("[PA/1.00/2]"));
+
+    let games = parse_ggf(&text);
+    assert_eq!(games.len(), 1);
+    let read = games[0].as_ref().unwrap();
+    assert_eq!(read, &game);
+    assert_eq!(read.tag("PB"), Some("a]b\\c"));
+    assert_eq!(read.winner(), Some(true));
+    assert_eq!(read.replay(), Ok(board));
+    assert_eq!(read.to_ggf(), text);
+}
+
+#[test]
+fn reads_non_standard_start() {
+    // 白番から始まる, f5 を打った後の局面
+    let text = "(;GM[Othello]BO[8 ---------------------------O*------***-------------------------- O]W[d6//3];)";
+    let games = parse_ggf(text);
+    let game = games[0].as_ref().unwrap();
+    assert!(!game.is_standard_start());
+    assert!(!game.color);
+    assert_eq!(game.moves, vec![(false, Move::Mv(3, 5), "//3".to_string())]);
+    let board = game.replay().unwrap();
+    assert_eq!(board.0.count_ones() + board.1.count_ones(), 6);
+    assert_eq!(game.to_ggf().matches("BO[").count(), 1);
+    assert_eq!(parse_ggf(&game.to_ggf())[0].as_ref(), Ok(game));
+}
+
+#[test]
+fn skips_bad_games_and_reports_unterminated() {
+    let text = "junk (;GM[Othello]B[z9];) (;GM[Othello]B[f5]W[d6];)\n(;GM[Othello]PB[x";
+    let games = parse_ggf(text);
+    assert_eq!(games.len(), 3);
+    assert_eq!(games[0], Err(GgfError::InvalidMove(1, "z9".to_string())));
+    assert_eq!(games[1].as_ref().unwrap().moves.len(), 2);
+    assert_eq!(games[2], Err(GgfError::Unterminated));
+}
+
+#[test]
+fn rejects_illegal_moves_on_replay() {
+    let games = parse_ggf("(;GM[Othello]B[f5]W[c4];)");
+    let game = games[0].as_ref().unwrap();
+    assert_eq!(
+        game.replay(),
+        Err(GgfError::IllegalMove(2, "C4".to_string()))
+    );
+}
@@ -0,0 +1,25 @@
+use reversi_rust::play::parse_move_list;
+use reversi_rust::query::parse_position;
+
+// 手順と盤面の文字列は同じ局面になる. 大文字小文字と空白は問わない
+#[test]
+fn parses_moves_and_squares() {
+    let (board, color) = parse_position("f5D6 c3").unwrap();
+    assert!(!color);
+    let squares =
+        "--------\n--------\n--X-----\n---XX---\n---OXX--\n---O----\n--------\n-------- o";
+    assert_eq!(parse_position(squares), Some((board, color)));
+    assert_eq!(parse_position("f5f5"), None);
+    assert_eq!(parse_position("f5d"), None);
+    assert_eq!(parse_position("f5\u{80}\u{80}"), None);
+    assert_eq!(parse_position("f5d0"), None);
+}
+
+// 範囲外の文字は溢れずに不正な手順として扱う
+#[test]
+fn rejects_bytes_outside_the_board() {
+    assert_eq!(parse_move_list("f5\u{80}\u{80}"), None);
+    assert_eq!(parse_move_list("\u{ff}5"), None);
+    assert_eq!(parse_move_list("i5"), None);
+    assert_eq!(parse_move_list("F5d6").map(|m| m.len()), Some(2));
+}
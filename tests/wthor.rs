@@ -0,0 +1,35 @@
+use reversi_rust::book::Book;
+use reversi_rust::parse::import_wthor;
+use reversi_rust::wthor::load_wthor;
+use reversi_rust::Move;
+
+const FIXTURE: &str = "tests/fixtures/tiny.wtb";
+
+#[test]
+fn replays_fixture_games() {
+    let (header, games) = load_wthor(FIXTURE).unwrap();
+    assert_eq!(header.games, 3);
+    assert_eq!(header.year, 2026);
+    assert_eq!(games.len(), 3);
+
+    let replayed = games
+        .iter()
+        .map(|game| game.replay().unwrap().0)
+        .collect::<Vec<_>>();
+    // 2局目は途中にパスがあり, 3局目は盤面が埋まる前に終わる
+    assert!(!replayed[0].contains(&Move::Pass));
+    assert!(replayed[1].contains(&Move::Pass));
+    assert_eq!(games[2].squares.len(), 59);
+    assert_eq!(games[0].winner(), Some(false));
+    assert_eq!(games[2].winner(), Some(true));
+}
+
+#[test]
+fn imports_fixture_into_book() {
+    let mut book = Book::new();
+    let summary = import_wthor(&mut book, FIXTURE, 20).unwrap();
+    assert_eq!(summary.imported, 3);
+    assert_eq!(summary.rejected, 0);
+    let games = book.iter().map(|(_, (w, l))| w + l).sum::<i32>();
+    assert_eq!(games, 3 * 20);
+}